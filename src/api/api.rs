@@ -1,128 +1,130 @@
-use std::collections::HashMap;
-use actix_web::{web, get};
-use actix_web::HttpResponse;
-use repository::database::Database;
-use crate::repository;
+use actix_web::{web, get, put};
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::Utc;
+use crate::api::stream;
+use crate::error::ApiError;
+use crate::feed::Feed;
+use crate::indicators::IndicatorQuery;
+use crate::ingest::Ingestor;
+use crate::models::instrument::{ChartQuery, InstrumentKey, InstrumentQuery, MoverDirection, MoversQuery, SearchQuery, UpdatePayload};
+use crate::repository::repository::{MarketDataRepository, RepositoryError};
 
 #[get("/instruments")]
-pub async fn get_instruments(db: web::Data<Database>) -> HttpResponse {
-    let instruments = match db.load().await {
-        Ok(instrument_list) => {
-            HttpResponse::Ok().json(instrument_list)
-        },
-        Err(error) => {
-            eprintln!("error: {:?}", error);
-            HttpResponse::NotFound().body("error".to_string())
-        }
-    };
-
-    instruments
+pub async fn get_instruments(db: web::Data<dyn MarketDataRepository>, query: web::Query<InstrumentQuery>) -> Result<HttpResponse, ApiError> {
+    let listing = query.resolve().map_err(ApiError::BadRequest)?;
+    let page = db.load(&listing).await?;
+    Ok(HttpResponse::Ok().json(page))
 }
 
 #[get("/instruments/search")]
-pub async fn search_instruments(db: web::Data<Database>, query: web::Query<HashMap<String, String>>) -> HttpResponse {
-    let search_term = query.get("q").unwrap_or(&"".to_string()).clone();
-    match db.search_instruments(search_term).await {
-        Ok(instrument_list) => {
-            HttpResponse::Ok().json(instrument_list)
-        },
-        Err(error) => {
-            eprintln!("error: {:?}", error);
-            HttpResponse::NotFound().body("error".to_string())
-        }
-    }
+pub async fn search_instruments(db: web::Data<dyn MarketDataRepository>, query: web::Query<SearchQuery>) -> Result<HttpResponse, ApiError> {
+    let request = query.resolve().map_err(ApiError::BadRequest)?;
+    let instrument_list = db.search_instruments(&request).await?;
+    Ok(HttpResponse::Ok().json(instrument_list))
+}
+
+async fn respond_with_movers(db: &dyn MarketDataRepository, query: MoversQuery) -> Result<HttpResponse, ApiError> {
+    let request = query.resolve().map_err(ApiError::BadRequest)?;
+    let instrument_list = db.movers(&request).await?;
+    Ok(HttpResponse::Ok().json(instrument_list))
+}
+
+#[get("/instruments/movers")]
+pub async fn movers(db: web::Data<dyn MarketDataRepository>, query: web::Query<MoversQuery>) -> Result<HttpResponse, ApiError> {
+    respond_with_movers(db.get_ref(), query.into_inner()).await
 }
 
 #[get("/instruments/top-losers")]
-pub async fn top_losers(db: web::Data<Database>) -> HttpResponse {
-    match db.top_losers().await {
-        Ok(instrument_list) => {
-            HttpResponse::Ok().json(instrument_list)
-        },
-        Err(error) => {
-            eprintln!("error: {:?}", error);
-            HttpResponse::NotFound().body("error".to_string())
-        }
-    }
+pub async fn top_losers(db: web::Data<dyn MarketDataRepository>, query: web::Query<MoversQuery>) -> Result<HttpResponse, ApiError> {
+    let query = MoversQuery { direction: Some(MoverDirection::Losers), ..query.into_inner() };
+    respond_with_movers(db.get_ref(), query).await
 }
 
 
 #[get("/instruments/top-gainers")]
-pub async fn top_gainers(db: web::Data<Database>) -> HttpResponse {
-    match db.top_gainers().await {
-        Ok(instrument_list) => {
-            HttpResponse::Ok().json(instrument_list)
-        },
-        Err(error) => {
-            eprintln!("error: {:?}", error);
-            HttpResponse::NotFound().body("error".to_string())
-        }
-    }
+pub async fn top_gainers(db: web::Data<dyn MarketDataRepository>, query: web::Query<MoversQuery>) -> Result<HttpResponse, ApiError> {
+    let query = MoversQuery { direction: Some(MoverDirection::Gainers), ..query.into_inner() };
+    respond_with_movers(db.get_ref(), query).await
 }
 
 
-#[get("/instrument/{id}")]
-pub async fn get_instrument_by_id(db: web::Data<Database>, id: web::Path<i64>) -> HttpResponse {
-    let instrument_id = id.into_inner();
-    let instrument = match db.get_instrument_by_id(instrument_id).await {
-        Ok(instrument_detail) => {
-            HttpResponse::Ok().json(instrument_detail)
-        },
-        Err(error) => {
-            eprintln!("error: {:?}", error);
-            HttpResponse::NotFound().body("error".to_string())
-        }
+#[get("/instrument/{key}")]
+pub async fn get_instrument_by_id(db: web::Data<dyn MarketDataRepository>, key: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let key = InstrumentKey::from(key.as_str());
+    let instrument_detail = match (db.get_instrument_detail(&key).await, key.as_symbol()) {
+        // No instrument has that ID, but an all-digit code might be what was meant.
+        (Err(RepositoryError::NotFound), Some(symbol)) => db.get_instrument_detail(&symbol).await?,
+        (detail, _) => detail?,
     };
+    Ok(HttpResponse::Ok().json(instrument_detail))
+}
 
-    instrument
+#[put("/instrument")]
+pub async fn update_instrument_by_id(ingestor: web::Data<Ingestor>, req: HttpRequest, payload: web::Json<UpdatePayload>) -> Result<HttpResponse, ApiError> {
+    let api_key = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError::Unauthorized("missing bearer API key".to_string()))?;
+    let client_id = ingestor.authenticate(api_key)
+        .ok_or_else(|| ApiError::Unauthorized("unknown API key".to_string()))?;
+
+    let update = ingestor.ingest(client_id, payload.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(update))
+}
+
+#[get("/stats/pool")]
+pub async fn get_pool_stats(db: web::Data<dyn MarketDataRepository>) -> Result<HttpResponse, ApiError> {
+    let stats = db.pool_stats()
+        .ok_or_else(|| ApiError::NotFound("backend has no connection pool".to_string()))?;
+    Ok(HttpResponse::Ok().json(stats))
 }
 
-// #[put("/instrument")]
-// pub async fn update_instrument_by_id(db: web::Data<Database>, payload: web::Json<UpdatePayload>) -> HttpResponse {
-//     let payload = payload.into_inner().clone();
-    
-//     if payload.client_id == "OMS_SERVER" {
-//         match db.update_instrument_by_id(payload.instrument.instrument_id, payload.instrument.clone()).await {
-//             Ok(_) => {
-//                 HttpResponse::Ok().json("data base update success ")
-//             },
-//             Err(e) => {
-//                 HttpResponse::NotFound().body(e.to_string())
-//             }
-//         }
-//     } else {
-//         HttpResponse::NotFound().body("error".to_string())
-//     }
-
-// }
+#[get("/stats/feed")]
+pub async fn get_feed_stats(feed: web::Data<Feed>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(feed.stats()))
+}
 
 #[get("/instrument/charts/{id}")]
-pub async fn get_chart_data_by_id(db: web::Data<Database>, id: web::Path<i64>) -> HttpResponse {
+pub async fn get_chart_data_by_id(db: web::Data<dyn MarketDataRepository>, id: web::Path<i64>, query: web::Query<ChartQuery>) -> Result<HttpResponse, ApiError> {
     let instrument_id = id.into_inner();
-    let instrument = match db.get_chart_data_by_id(instrument_id).await {
-        Ok(chart_data) => {
-            println!("chart_data {:?}", chart_data);
-            HttpResponse::Ok().json(chart_data)
-        },
-        Err(error) => {
-            eprintln!("error: {:?}", error);
-            HttpResponse::NotFound().body("error".to_string())
-        }
-    };
-
-    instrument
+    let range = query.resolve(Utc::now().naive_utc()).map_err(ApiError::BadRequest)?;
+    let chart_data = db.get_chart_data_by_id(instrument_id, &range).await?;
+    Ok(HttpResponse::Ok().json(chart_data))
 }
 
+#[get("/instrument/indicators/{id}")]
+pub async fn get_indicator_by_id(db: web::Data<dyn MarketDataRepository>, id: web::Path<i64>, query: web::Query<IndicatorQuery>) -> Result<HttpResponse, ApiError> {
+    let instrument_id = id.into_inner();
+    let indicator = query.resolve().map_err(ApiError::BadRequest)?;
+    let range = query.chart().resolve(Utc::now().naive_utc()).map_err(ApiError::BadRequest)?;
+    // Bars before `from` are only there to warm the indicator up, and are cut off again after computing it.
+    let chart_data = db.get_chart_data_by_id(instrument_id, &range.extended_back(indicator.warmup_bars())).await?;
+    let first_bar = range.interval.bucket_start(range.from);
+    let in_range = chart_data.iter().filter(|bar| bar.timestamp >= first_bar).count();
+    let warmup = chart_data.len() - in_range.min(range.limit as usize);
+    Ok(HttpResponse::Ok().json(indicator.compute(instrument_id, range.interval, &chart_data).skip(warmup)))
+}
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/api")
+            // Malformed paths and query strings get the same JSON error body as handler errors.
+            .app_data(web::PathConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()))
+            .app_data(web::QueryConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()))
+            .app_data(web::JsonConfig::default().error_handler(|err, _| ApiError::BadRequest(err.to_string()).into()))
             .service(get_instruments)
-            // .service(update_instrument_by_id)
+            .service(update_instrument_by_id)
             .service(get_instrument_by_id)
             .service(search_instruments)
             .service(top_losers)
             .service(top_gainers)
+            .service(movers)
             .service(get_chart_data_by_id)
+            .service(get_indicator_by_id)
+            .service(get_pool_stats)
+            .service(get_feed_stats)
+            .service(stream::stream_prices)
     );
 }
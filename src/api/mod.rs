@@ -1 +1,5 @@
-pub mod api;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod api;
+pub mod number_format;
+pub mod request_id;
+pub mod stream;
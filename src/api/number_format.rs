@@ -0,0 +1,35 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+use crate::models::decimal::{with_number_format, NumberFormat};
+
+#[derive(Deserialize)]
+struct NumbersQuery {
+    numbers: Option<String>,
+}
+
+/// Picks how decimals are written for this request: exact strings by default,
+/// or JSON floats for older clients that pass `?numbers=float`.
+pub async fn number_format(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let requested = web::Query::<NumbersQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().numbers);
+    let format = match requested.as_deref() {
+        None => NumberFormat::default(),
+        Some(value) => match NumberFormat::from_param(value) {
+            Some(format) => format,
+            None => {
+                // Rendered here, inside the request ID scope, so the body carries the ID.
+                let error = ApiError::BadRequest(format!("numbers must be \"exact\" or \"float\", got {:?}", value));
+                return Ok(req.into_response(error.error_response()).map_into_right_body());
+            },
+        },
+    };
+
+    let res = with_number_format(format, next.call(req)).await?;
+    Ok(res.map_into_left_body())
+}
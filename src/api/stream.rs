@@ -0,0 +1,214 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use futures::stream;
+use serde::de::{value, IntoDeserializer};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, error::SendError, Sender};
+
+use crate::bars::Bar;
+use crate::error::ApiError;
+use crate::feed::{Feed, FeedEvent, FeedPayload};
+use crate::models::decimal::{number_format, with_number_format};
+use crate::models::instrument::{ChartInterval, InstrumentKey};
+use crate::models::protocol::ServerMessage;
+use crate::repository::repository::MarketDataRepository;
+use crate::shutdown::Shutdown;
+use crate::websocket::{take_wildcard, Subscriptions};
+
+/// How often an idle stream gets a comment line, so proxies don't time the connection out.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Frames queued per client. Once full the client falls behind the feed and gets a snapshot when it overruns it.
+const STREAM_BACKLOG: usize = 64;
+
+/// Query parameters accepted by the event stream. Lists are comma-separated.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct StreamQuery {
+    /// IDs, codes or symbols as in a WebSocket `subscribe`, or `*` for every instrument.
+    pub instruments: Option<String>,
+    /// Bar intervals to stream for those instruments, e.g. `1m,5m`.
+    pub bars: Option<String>,
+}
+
+/// An event stream request with its lists parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamRequest {
+    pub all: bool,
+    pub keys: Vec<InstrumentKey>,
+    pub bars: Vec<ChartInterval>,
+}
+
+fn split_list(list: Option<&str>) -> impl Iterator<Item = &str> {
+    list.unwrap_or_default().split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+impl StreamQuery {
+    pub fn resolve(&self) -> Result<StreamRequest, String> {
+        let keys: Vec<InstrumentKey> = split_list(self.instruments.as_deref()).map(InstrumentKey::from).collect();
+        if keys.is_empty() {
+            return Err("instruments is required, e.g. instruments=AAPL,MSFT or instruments=*".to_string());
+        }
+        let (all, keys) = take_wildcard(keys);
+
+        let bars = split_list(self.bars.as_deref())
+            .map(|interval| {
+                ChartInterval::deserialize(IntoDeserializer::<value::Error>::into_deserializer(interval))
+                    .map_err(|_| format!("unknown bar interval {:?}", interval))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(StreamRequest { all, keys, bars })
+    }
+}
+
+/// Server-Sent Events version of the WebSocket subscriber feed, for clients that can only speak HTTP.
+/// Each event's data is the WebSocket message, and its ID the feed sequence number, so a reconnecting
+/// client's `Last-Event-ID` resumes from the feed's replay buffer.
+#[get("/stream")]
+pub async fn stream_prices(req: HttpRequest, feed: web::Data<Feed>, db: web::Data<dyn MarketDataRepository>, shutdown: web::Data<Shutdown>, query: web::Query<StreamQuery>) -> Result<HttpResponse, ApiError> {
+    let request = query.resolve().map_err(ApiError::BadRequest)?;
+    let ids = db.resolve_path_keys(&request.keys).await?;
+    if !request.all && ids.is_empty() {
+        return Err(ApiError::NotFound("none of the instruments exist".to_string()));
+    }
+
+    let last_event_id = req.headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    // Without usable history to replay the client starts over from a snapshot.
+    let (replay, events) = match last_event_id {
+        Some(seq) => feed.resume(seq),
+        None => (None, feed.subscribe()),
+    };
+
+    let subscriptions = Subscriptions { all: request.all, ids: ids.iter().copied().collect(), bars: request.bars.into_iter().collect() };
+    let (frames, queued) = mpsc::channel(STREAM_BACKLOG);
+    let client = StreamClient { frames, subscriptions, ids, feed: feed.into_inner(), repository: db.into_inner() };
+    // The body is polled outside the request's task, so frames are serialized in the pump, in the request's format.
+    actix_web::rt::spawn(with_number_format(number_format(), client.pump(events, replay, shutdown.into_inner())));
+
+    let body = stream::unfold(queued, |mut queued| async move {
+        queued.recv().await.map(|frame| (Ok::<_, Infallible>(frame), queued))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Keeps nginx-style proxies from buffering the stream.
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body))
+}
+
+/// One event stream connection. Sends fail once the client has gone away.
+struct StreamClient {
+    frames: Sender<Bytes>,
+    subscriptions: Subscriptions,
+    ids: Vec<i64>,
+    feed: Arc<Feed>,
+    repository: Arc<dyn MarketDataRepository>,
+}
+
+impl StreamClient {
+    /// Writes the subscribed feed until the client disconnects or the server shuts down.
+    async fn pump(self, events: broadcast::Receiver<FeedEvent>, replay: Option<Vec<FeedEvent>>, shutdown: Arc<Shutdown>) {
+        // Dropping the sender ends the response, which lets graceful shutdown finish.
+        let _ = self.run(events, replay, &shutdown).await;
+    }
+
+    async fn run(&self, mut events: broadcast::Receiver<FeedEvent>, replay: Option<Vec<FeedEvent>>, shutdown: &Shutdown) -> Result<(), SendError<Bytes>> {
+        let bars = self.subscriptions.bar_intervals();
+        self.send(None, &ServerMessage::Subscribe { instruments: self.ids.clone(), all: self.subscriptions.all, bars }).await?;
+        match replay {
+            Some(replay) => {
+                for event in replay {
+                    self.forward(event).await?;
+                }
+            },
+            None => self.send_snapshot().await?,
+        }
+
+        let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => self.forward(event).await?,
+                    Err(RecvError::Lagged(skipped)) => {
+                        eprintln!("Event stream client missed {} feed events", skipped);
+                        self.feed.record_lag();
+                        self.send_snapshot().await?;
+                    },
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = keepalive.tick() => self.frames.send(Bytes::from_static(b": keepalive\n\n")).await?,
+                _ = self.frames.closed() => return Ok(()),
+                _ = shutdown.wait() => return Ok(()),
+            }
+        }
+    }
+
+    /// Sends the messages `event` produces for this client. Only the last carries the event ID,
+    /// so a client cut off part way through a bar batch gets the whole batch again on resuming.
+    async fn forward(&self, event: FeedEvent) -> Result<(), SendError<Bytes>> {
+        let messages = match event.payload {
+            FeedPayload::Price(update) => {
+                if !self.subscriptions.wants(i64::from(update.instrument_id)) {
+                    return Ok(());
+                }
+                vec![ServerMessage::Update { seq: event.seq, instrument: update }]
+            },
+            FeedPayload::Bars(batch) => {
+                let wanted = |bar: &&Bar| self.subscriptions.wants_bar(bar);
+                let closed = batch.closed.iter().filter(wanted).map(|bar| ServerMessage::BarClose { seq: event.seq, bar: bar.clone() });
+                let updated = batch.updated.iter().filter(wanted).map(|bar| ServerMessage::BarUpdate { seq: event.seq, bar: bar.clone() });
+                closed.chain(updated).collect()
+            },
+            FeedPayload::Gap { down_for_ms } => {
+                self.send(Some(event.seq), &ServerMessage::Gap { seq: event.seq, down_for_ms }).await?;
+                return self.send_snapshot().await;
+            },
+        };
+
+        let count = messages.len();
+        for (i, message) in messages.iter().enumerate() {
+            let id = (i + 1 == count).then_some(event.seq);
+            self.send(id, message).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the current state of the subscribed instruments, every one for `*`, as the WebSocket does on subscribing.
+    async fn send_snapshot(&self) -> Result<(), SendError<Bytes>> {
+        if !self.subscriptions.all && self.ids.is_empty() {
+            return Ok(());
+        }
+
+        let seq = self.feed.last_seq();
+        let instruments = if self.subscriptions.all {
+            self.repository.get_all_instruments().await
+        } else {
+            self.repository.get_instruments_by_ids(&self.ids).await
+        };
+        match instruments {
+            Ok(instruments) => self.send(Some(seq), &ServerMessage::Snapshot { seq, instruments }).await,
+            Err(e) => {
+                eprintln!("error loading snapshot: {:?}", e);
+                self.send(None, &ServerMessage::Error { message: "could not load snapshot".to_string() }).await
+            },
+        }
+    }
+
+    async fn send(&self, id: Option<u64>, message: &ServerMessage) -> Result<(), SendError<Bytes>> {
+        // Messages hold only strings, numbers and maps, so serializing cannot fail.
+        let data = serde_json::to_string(message).unwrap_or_default();
+        let frame = match id {
+            Some(id) => format!("id: {}\ndata: {}\n\n", id, data),
+            None => format!("data: {}\n\n", data),
+        };
+        self.frames.send(Bytes::from(frame)).await
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, error::TrySendError, Sender};
+use tokio::task::JoinHandle;
+
+use crate::feed::{Feed, FeedPayload};
+use crate::models::decimal::Decimal;
+use crate::models::instrument::{ChartData, ChartInterval, InstrumentUpdate};
+use crate::repository::repository::MarketDataRepository;
+use crate::shutdown::Shutdown;
+
+/// How often bars changed since the last batch are published, and bars past their end closed.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Closed bars waiting to be stored. Past this the database is far behind, and new bars are dropped.
+const PERSIST_BACKLOG: usize = 4096;
+
+/// The only interval persisted. Charts aggregate coarser bars from these rows on read.
+const PERSISTED_INTERVAL: ChartInterval = ChartInterval::Min1;
+
+/// An OHLC bar built from live price updates. Field names match `ChartData`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Bar {
+    pub instrument_id: i64,
+    pub interval: ChartInterval,
+    /// Start of the bar.
+    pub timestamp: NaiveDateTime,
+    pub open_price: Decimal,
+    pub high_price: Decimal,
+    pub low_price: Decimal,
+    pub close_price: Decimal,
+    /// Always null, as price updates carry no traded size.
+    pub volume: Option<Decimal>,
+    /// Price updates folded into the bar.
+    pub ticks: u64,
+}
+
+impl Bar {
+    fn open(instrument_id: i64, interval: ChartInterval, timestamp: NaiveDateTime, price: &Decimal) -> Self {
+        Bar {
+            instrument_id,
+            interval,
+            timestamp,
+            open_price: price.clone(),
+            high_price: price.clone(),
+            low_price: price.clone(),
+            close_price: price.clone(),
+            volume: None,
+            ticks: 1,
+        }
+    }
+
+    fn apply(&mut self, price: &Decimal) {
+        if *price > self.high_price {
+            self.high_price = price.clone();
+        }
+        if *price < self.low_price {
+            self.low_price = price.clone();
+        }
+        self.close_price = price.clone();
+        self.ticks += 1;
+    }
+
+    /// The bar as a `market_data_chart` row. The ID is assigned when it is stored.
+    pub fn to_chart_data(&self) -> ChartData {
+        ChartData {
+            chart_data_id: 0,
+            instrument_id: self.instrument_id,
+            open_price: Some(self.open_price.clone()),
+            close_price: Some(self.close_price.clone()),
+            high_price: Some(self.high_price.clone()),
+            low_price: Some(self.low_price.clone()),
+            volume: self.volume.clone(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// Bars published together as one feed event. Closed bars come before updated ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BarBatch {
+    /// Bars that are final and will not change again.
+    pub closed: Vec<Bar>,
+    /// In-progress bars that changed since the previous batch.
+    pub updated: Vec<Bar>,
+}
+
+impl BarBatch {
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty() && self.updated.is_empty()
+    }
+}
+
+type BarKey = (i64, ChartInterval);
+
+/// Rolling bars for every instrument and `ChartInterval`.
+/// A bar opens with the first update inside its interval and closes once time moves past its end.
+#[derive(Default)]
+pub struct BarBuilder {
+    open: BTreeMap<BarKey, Bar>,
+    /// Open bars changed since the last batch.
+    changed: BTreeSet<BarKey>,
+    /// Bars closed since the last batch.
+    closed: Vec<Bar>,
+}
+
+impl BarBuilder {
+    pub fn new() -> Self {
+        BarBuilder::default()
+    }
+
+    /// Folds `update`, received at `now`, into the current bar of every interval.
+    pub fn apply(&mut self, update: &InstrumentUpdate, now: NaiveDateTime) {
+        let instrument_id = i64::from(update.instrument_id);
+        for interval in ChartInterval::ALL {
+            let key = (instrument_id, interval);
+            let start = interval.bucket_start(now);
+            match self.open.get_mut(&key) {
+                Some(bar) if bar.timestamp == start => bar.apply(&update.last_price),
+                _ => {
+                    let bar = Bar::open(instrument_id, interval, start, &update.last_price);
+                    if let Some(previous) = self.open.insert(key, bar) {
+                        self.closed.push(previous);
+                    }
+                },
+            }
+            self.changed.insert(key);
+        }
+    }
+
+    /// Closes every bar ending at or before `now` and returns what changed since the last batch.
+    pub fn take_batch(&mut self, now: NaiveDateTime) -> BarBatch {
+        let due: Vec<BarKey> = self.open
+            .iter()
+            .filter(|(_, bar)| bar.interval.bucket_end(bar.timestamp) <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in due {
+            if let Some(bar) = self.open.remove(&key) {
+                self.changed.remove(&key);
+                self.closed.push(bar);
+            }
+        }
+
+        let updated = std::mem::take(&mut self.changed)
+            .into_iter()
+            .filter_map(|key| self.open.get(&key).cloned())
+            .collect();
+        BarBatch { closed: std::mem::take(&mut self.closed), updated }
+    }
+}
+
+/// Builds bars from the feed's price updates and publishes a batch back to it every second, until shutdown.
+/// With `persist`, closed one-minute bars are also stored as chart rows, by a writer the builder waits for on shutdown.
+pub fn spawn_bar_builder(feed: Arc<Feed>, repository: Arc<dyn MarketDataRepository>, persist: bool, shutdown: Arc<Shutdown>) -> JoinHandle<()> {
+    let mut rx = feed.subscribe();
+    tokio::spawn(async move {
+        let writer = persist.then(|| spawn_bar_writer(repository));
+        let mut builder = BarBuilder::new();
+        let mut publish = tokio::time::interval(PUBLISH_INTERVAL);
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(event) => {
+                        if let FeedPayload::Price(update) = event.payload {
+                            builder.apply(&update, Utc::now().naive_utc());
+                        }
+                    },
+                    Err(RecvError::Lagged(skipped)) => eprintln!("Bar builder missed {} feed events", skipped),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = publish.tick() => {
+                    let batch = builder.take_batch(Utc::now().naive_utc());
+                    if batch.is_empty() {
+                        continue;
+                    }
+                    if let Some((bars, _)) = &writer {
+                        for bar in batch.closed.iter().filter(|bar| bar.interval == PERSISTED_INTERVAL) {
+                            if let Err(TrySendError::Full(bar)) = bars.try_send(bar.clone()) {
+                                eprintln!("Bar writer is behind, dropping bar for instrument {} at {}", bar.instrument_id, bar.timestamp);
+                            }
+                        }
+                    }
+                    feed.publish(FeedPayload::Bars(Arc::new(batch)));
+                },
+                _ = shutdown.wait() => break,
+            }
+        }
+
+        // Closing the channel lets the writer finish what is queued and stop.
+        if let Some((bars, writer)) = writer {
+            drop(bars);
+            let _ = writer.await;
+        }
+    })
+}
+
+/// Stores closed bars in the order they arrive, so a slow database never holds up publishing.
+fn spawn_bar_writer(repository: Arc<dyn MarketDataRepository>) -> (Sender<Bar>, JoinHandle<()>) {
+    let (bars, mut queued) = mpsc::channel::<Bar>(PERSIST_BACKLOG);
+    let writer = tokio::spawn(async move {
+        while let Some(bar) = queued.recv().await {
+            if let Err(e) = repository.insert_chart_bar(&bar.to_chart_data()).await {
+                eprintln!("error storing bar for instrument {}: {:?}", bar.instrument_id, e);
+            }
+        }
+    });
+    (bars, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 2).unwrap().and_hms_opt(hour, minute, second).unwrap()
+    }
+
+    fn update(instrument_id: i32, price: i64) -> InstrumentUpdate {
+        InstrumentUpdate {
+            instrument_id,
+            last_price: Decimal::from(price),
+            prev_price: Decimal::from(price),
+            change: Decimal::from(0),
+        }
+    }
+
+    fn minute_bars(bars: &[Bar]) -> Vec<&Bar> {
+        bars.iter().filter(|bar| bar.interval == ChartInterval::Min1).collect()
+    }
+
+    #[test]
+    fn apply_folds_updates_into_ohlc() {
+        let mut builder = BarBuilder::new();
+        for (second, price) in [(1, 10), (20, 12), (40, 9), (59, 11)] {
+            builder.apply(&update(1, price), at(9, 30, second));
+        }
+
+        let batch = builder.take_batch(at(9, 30, 59));
+        assert!(batch.closed.is_empty());
+        assert_eq!(batch.updated.len(), ChartInterval::ALL.len());
+        let bar = minute_bars(&batch.updated)[0];
+        assert_eq!(bar.timestamp, at(9, 30, 0));
+        assert_eq!(
+            (&bar.open_price, &bar.high_price, &bar.low_price, &bar.close_price),
+            (&Decimal::from(10), &Decimal::from(12), &Decimal::from(9), &Decimal::from(11))
+        );
+        assert_eq!(bar.ticks, 4);
+
+        // Nothing changed since, so the next batch is empty.
+        assert!(builder.take_batch(at(9, 30, 59)).is_empty());
+    }
+
+    #[test]
+    fn update_in_the_next_bucket_rolls_the_bar_over() {
+        let mut builder = BarBuilder::new();
+        builder.apply(&update(1, 10), at(9, 30, 10));
+        builder.apply(&update(1, 11), at(9, 31, 5));
+
+        // Taken before the minute is up, so only the rolled-over bar is closed.
+        let batch = builder.take_batch(at(9, 31, 5));
+        let closed = minute_bars(&batch.closed);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].timestamp, at(9, 30, 0));
+        assert_eq!(closed[0].close_price, Decimal::from(10));
+        assert_eq!(batch.closed.len(), 1, "coarser bars are still open");
+
+        let updated = minute_bars(&batch.updated);
+        assert_eq!(updated[0].timestamp, at(9, 31, 0));
+        assert_eq!(updated[0].open_price, Decimal::from(11));
+    }
+
+    #[test]
+    fn take_batch_closes_bars_past_their_end() {
+        let mut builder = BarBuilder::new();
+        builder.apply(&update(1, 10), at(9, 30, 10));
+        builder.apply(&update(2, 20), at(9, 30, 20));
+        builder.take_batch(at(9, 30, 30));
+
+        // No updates arrive, but time moves past the minute and the five-minute bucket.
+        let batch = builder.take_batch(at(9, 35, 0));
+        let mut closed: Vec<(i64, ChartInterval)> = batch.closed.iter().map(|bar| (bar.instrument_id, bar.interval)).collect();
+        closed.sort();
+        assert_eq!(closed, vec![(1, ChartInterval::Min1), (1, ChartInterval::Min5), (2, ChartInterval::Min1), (2, ChartInterval::Min5)]);
+        assert!(batch.updated.is_empty());
+
+        // A closed bar is only reported once.
+        assert!(builder.take_batch(at(9, 36, 0)).closed.is_empty());
+    }
+}
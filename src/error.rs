@@ -0,0 +1,122 @@
+use std::fmt;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use bb8::RunError;
+use serde::Serialize;
+
+use crate::api::request_id::current_request_id;
+use crate::ingest::IngestError;
+use crate::repository::repository::RepositoryError;
+use crate::validation::Rejection;
+
+/// Errors surfaced by the HTTP API. Each variant maps to one status code and a JSON body.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Unavailable(String),
+    Internal(String),
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            code: self.code(),
+            message: self.message().to_string(),
+            request_id: current_request_id(),
+        })
+    }
+}
+
+impl From<RepositoryError> for ApiError {
+    /// Lost connections and pool timeouts become 503s; anything else unexpected is a 500.
+    /// Details are logged against the request ID rather than returned to the caller.
+    fn from(error: RepositoryError) -> Self {
+        let api_error = match &error {
+            RepositoryError::NotFound => return ApiError::NotFound("instrument not found".to_string()),
+            RepositoryError::Database(RunError::TimedOut) => ApiError::Unavailable("database unavailable".to_string()),
+            RepositoryError::Database(RunError::User(e)) if is_connection_error(e) => {
+                ApiError::Unavailable("database unavailable".to_string())
+            },
+            RepositoryError::Database(_) | RepositoryError::Fixture(_) => ApiError::Internal("internal error".to_string()),
+        };
+        eprintln!("[{}] {}: {:?}", current_request_id().unwrap_or_default(), api_error.code(), error);
+        api_error
+    }
+}
+
+impl From<IngestError> for ApiError {
+    fn from(error: IngestError) -> Self {
+        match error {
+            IngestError::WrongClient { .. } => ApiError::Forbidden(error.to_string()),
+            IngestError::Rejected(Rejection::LookupFailed(_)) => ApiError::Unavailable("database unavailable".to_string()),
+            IngestError::Rejected(rejection) => ApiError::BadRequest(rejection.to_string()),
+            IngestError::Repository(e) => e.into(),
+        }
+    }
+}
+
+/// True for failures to reach Postgres, as opposed to errors in the query itself.
+fn is_connection_error(error: &tokio_postgres::Error) -> bool {
+    if error.is_closed() {
+        return true;
+    }
+    if let Some(code) = error.code() {
+        // Class 08 connection exceptions, 53 insufficient resources, 57P operator intervention.
+        let code = code.code();
+        return code.starts_with("08") || code.starts_with("53") || code.starts_with("57P");
+    }
+    std::error::Error::source(error).is_some_and(|source| source.is::<std::io::Error>())
+}
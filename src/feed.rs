@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::bars::BarBatch;
+use crate::models::instrument::InstrumentUpdate;
+
+#[derive(Clone, Debug)]
+pub enum FeedPayload {
+    /// A validated price update, from NOTIFY or a publisher.
+    Price(InstrumentUpdate),
+    /// The NOTIFY listener was disconnected for `down_for_ms` and updates in that window were lost.
+    Gap { down_for_ms: u64 },
+    /// Bars built from price updates since the previous batch.
+    Bars(Arc<BarBatch>),
+}
+
+/// An event as broadcast to connected clients, stamped with its position in the feed.
+#[derive(Clone, Debug)]
+pub struct FeedEvent {
+    pub seq: u64,
+    pub payload: FeedPayload,
+}
+
+/// Counters of how often WebSocket clients fall behind the feed.
+#[derive(Default)]
+struct LagCounters {
+    lag_events: AtomicU64,
+    conflated_updates: AtomicU64,
+    lost_updates: AtomicU64,
+    resyncs: AtomicU64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FeedStats {
+    pub last_seq: u64,
+    /// Open receivers, one per subscribed client plus internal consumers such as the instrument cache.
+    pub receivers: usize,
+    /// Times a client fell behind, either filling its outbox or overrunning the broadcast channel.
+    pub lag_events: u64,
+    /// Updates a lagging client never received because a newer price for the instrument replaced them.
+    pub conflated_updates: u64,
+    /// Updates overwritten in the broadcast channel before a client read them.
+    pub lost_updates: u64,
+    /// Resync messages sent once a lagging client caught up.
+    pub resyncs: u64,
+}
+
+#[derive(Default)]
+struct Published {
+    seq: u64,
+    /// The most recent events, oldest first, for clients resuming after a reconnect.
+    replay: VecDeque<FeedEvent>,
+}
+
+/// Fan-out point between the NOTIFY listener and every client connection.
+/// Sequence numbers increase by one per published event, in broadcast order.
+pub struct Feed {
+    tx: Sender<FeedEvent>,
+    published: Mutex<Published>,
+    replay_capacity: usize,
+    lag: LagCounters,
+}
+
+impl Feed {
+    /// A feed buffering `capacity` events per receiver and keeping the last `replay_capacity` for resuming.
+    pub fn new(capacity: usize, replay_capacity: usize) -> Self {
+        let (tx, _rx) = broadcast::channel(capacity);
+        Feed { tx, published: Mutex::new(Published::default()), replay_capacity, lag: LagCounters::default() }
+    }
+
+    /// Stamps and broadcasts `payload`, returning its sequence number.
+    /// Events published while nobody is subscribed are dropped, but still kept for replay.
+    pub fn publish(&self, payload: FeedPayload) -> u64 {
+        // Hold the lock across the send so sequence order always matches channel order.
+        let mut published = self.published.lock().unwrap();
+        published.seq += 1;
+        let event = FeedEvent { seq: published.seq, payload };
+        if self.replay_capacity > 0 {
+            if published.replay.len() == self.replay_capacity {
+                published.replay.pop_front();
+            }
+            published.replay.push_back(event.clone());
+        }
+        let _ = self.tx.send(event);
+        published.seq
+    }
+
+    pub fn subscribe(&self) -> Receiver<FeedEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Subscribes together with the buffered events after `after_seq`, so the receiver continues exactly
+    /// where they end. The events are `None` when some after `after_seq` are no longer buffered,
+    /// or `after_seq` is ahead of the feed, as after a restart.
+    pub fn resume(&self, after_seq: u64) -> (Option<Vec<FeedEvent>>, Receiver<FeedEvent>) {
+        let published = self.published.lock().unwrap();
+        let rx = self.tx.subscribe();
+        let oldest = published.replay.front().map_or(published.seq + 1, |event| event.seq);
+        if after_seq > published.seq || after_seq + 1 < oldest {
+            return (None, rx);
+        }
+        let events = published.replay.iter().filter(|event| event.seq > after_seq).cloned().collect();
+        (Some(events), rx)
+    }
+
+    /// Sequence number of the most recently published event, 0 if none yet.
+    pub fn last_seq(&self) -> u64 {
+        self.published.lock().unwrap().seq
+    }
+
+    pub fn record_lag(&self) {
+        self.lag.lag_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a resync that replaced `conflated` updates and covered `lost` ones the client never saw.
+    pub fn record_resync(&self, conflated: u64, lost: u64) {
+        self.lag.resyncs.fetch_add(1, Ordering::Relaxed);
+        self.lag.conflated_updates.fetch_add(conflated, Ordering::Relaxed);
+        self.lag.lost_updates.fetch_add(lost, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> FeedStats {
+        FeedStats {
+            last_seq: self.last_seq(),
+            receivers: self.tx.receiver_count(),
+            lag_events: self.lag.lag_events.load(Ordering::Relaxed),
+            conflated_updates: self.lag.conflated_updates.load(Ordering::Relaxed),
+            lost_updates: self.lag.lost_updates.load(Ordering::Relaxed),
+            resyncs: self.lag.resyncs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publish_gaps(feed: &Feed, count: u64) {
+        for down_for_ms in 0..count {
+            feed.publish(FeedPayload::Gap { down_for_ms });
+        }
+    }
+
+    fn seqs(events: Option<Vec<FeedEvent>>) -> Option<Vec<u64>> {
+        events.map(|events| events.iter().map(|event| event.seq).collect())
+    }
+
+    #[test]
+    fn resume_replays_what_was_missed() {
+        let feed = Feed::new(16, 4);
+        publish_gaps(&feed, 6);
+        assert_eq!(seqs(feed.resume(3).0), Some(vec![4, 5, 6]));
+        // Caught up: nothing to replay, but no snapshot needed either.
+        assert_eq!(seqs(feed.resume(6).0), Some(vec![]));
+    }
+
+    #[test]
+    fn resume_continues_on_the_receiver() {
+        let feed = Feed::new(16, 4);
+        publish_gaps(&feed, 2);
+        let (replay, mut rx) = feed.resume(1);
+        assert_eq!(seqs(replay), Some(vec![2]));
+        feed.publish(FeedPayload::Gap { down_for_ms: 0 });
+        assert_eq!(rx.try_recv().unwrap().seq, 3);
+    }
+
+    #[test]
+    fn resume_past_the_replay_buffer_needs_a_snapshot() {
+        let feed = Feed::new(16, 4);
+        publish_gaps(&feed, 6);
+        // Events 3 to 6 are buffered, so 2 is the oldest position that can still resume.
+        assert_eq!(seqs(feed.resume(2).0), Some(vec![3, 4, 5, 6]));
+        assert_eq!(seqs(feed.resume(1).0), None);
+        assert_eq!(seqs(feed.resume(0).0), None);
+    }
+
+    #[test]
+    fn resume_from_the_future_needs_a_snapshot() {
+        let feed = Feed::new(16, 4);
+        publish_gaps(&feed, 2);
+        // As after a restart, when the client's last ID belongs to the previous process.
+        assert_eq!(seqs(feed.resume(10).0), None);
+    }
+
+    #[test]
+    fn resume_without_a_replay_buffer() {
+        let feed = Feed::new(16, 0);
+        assert_eq!(seqs(feed.resume(0).0), Some(vec![]));
+        publish_gaps(&feed, 1);
+        assert_eq!(seqs(feed.resume(1).0), Some(vec![]));
+        assert_eq!(seqs(feed.resume(0).0), None);
+    }
+}
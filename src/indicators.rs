@@ -0,0 +1,442 @@
+use std::collections::BTreeMap;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::models::instrument::{ChartData, ChartInterval, ChartQuery};
+
+/// Longest window any indicator may use.
+pub const MAX_INDICATOR_PERIOD: usize = 500;
+
+/// How many periods an exponential average is given to forget its seed before its values are used.
+const EMA_SETTLE_PERIODS: usize = 4;
+
+/// One value per input, `None` where the indicator is not yet defined.
+pub type Series = Vec<Option<f64>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndicatorName {
+    Sma,
+    Ema,
+    Rsi,
+    Macd,
+    Bollinger,
+}
+
+/// An indicator with every parameter filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum Indicator {
+    Sma { period: usize },
+    Ema { period: usize },
+    /// Wilder's relative strength index.
+    Rsi { period: usize },
+    Macd { fast: usize, slow: usize, signal: usize },
+    /// Bands `std_dev` population standard deviations around the `period` SMA.
+    Bollinger { period: usize, std_dev: f64 },
+}
+
+/// Query parameters accepted by the indicator endpoint: the chart range, the indicator,
+/// and its parameters. Parameters that do not apply to the indicator are ignored.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IndicatorQuery {
+    pub indicator: IndicatorName,
+    pub interval: Option<ChartInterval>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+    pub period: Option<usize>,
+    pub fast: Option<usize>,
+    pub slow: Option<usize>,
+    pub signal: Option<usize>,
+    pub std_dev: Option<f64>,
+}
+
+impl IndicatorQuery {
+    /// The chart the indicator is computed over.
+    pub fn chart(&self) -> ChartQuery {
+        ChartQuery { interval: self.interval, from: self.from, to: self.to, limit: self.limit }
+    }
+
+    /// Fills omitted parameters with the usual defaults and rejects ones that cannot be computed.
+    pub fn resolve(&self) -> Result<Indicator, String> {
+        let period = |default: usize| check_period("period", self.period.unwrap_or(default));
+        let indicator = match self.indicator {
+            IndicatorName::Sma => Indicator::Sma { period: period(20)? },
+            IndicatorName::Ema => Indicator::Ema { period: period(20)? },
+            IndicatorName::Rsi => Indicator::Rsi { period: period(14)? },
+            IndicatorName::Macd => {
+                let fast = check_period("fast", self.fast.unwrap_or(12))?;
+                let slow = check_period("slow", self.slow.unwrap_or(26))?;
+                let signal = check_period("signal", self.signal.unwrap_or(9))?;
+                if fast >= slow {
+                    return Err(format!("fast ({}) must be shorter than slow ({})", fast, slow));
+                }
+                Indicator::Macd { fast, slow, signal }
+            },
+            IndicatorName::Bollinger => {
+                let std_dev = self.std_dev.unwrap_or(2.0);
+                if !(std_dev.is_finite() && std_dev > 0.0) {
+                    return Err("std_dev must be a positive number".to_string());
+                }
+                Indicator::Bollinger { period: period(20)?, std_dev }
+            },
+        };
+        Ok(indicator)
+    }
+}
+
+fn check_period(name: &str, value: usize) -> Result<usize, String> {
+    if !(1..=MAX_INDICATOR_PERIOD).contains(&value) {
+        return Err(format!("{} must be between 1 and {}", name, MAX_INDICATOR_PERIOD));
+    }
+    Ok(value)
+}
+
+/// Indicator values aligned with the chart's bars: every series has one entry per timestamp.
+/// Entries are null until the indicator has seen enough bars, and for bars without a close.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IndicatorSeries {
+    pub instrument_id: i64,
+    pub interval: ChartInterval,
+    pub indicator: Indicator,
+    pub timestamps: Vec<NaiveDateTime>,
+    pub series: BTreeMap<&'static str, Series>,
+}
+
+impl Indicator {
+    /// Bars needed before a range for the indicator's first value in it to be defined and, for the
+    /// exponential ones, close to what a longer history would give.
+    pub fn warmup_bars(&self) -> usize {
+        match *self {
+            Indicator::Sma { period } | Indicator::Bollinger { period, .. } => period,
+            Indicator::Ema { period } | Indicator::Rsi { period } => period * EMA_SETTLE_PERIODS,
+            Indicator::Macd { slow, signal, .. } => (slow + signal) * EMA_SETTLE_PERIODS,
+        }
+    }
+
+    /// Computes the indicator over the closes of `bars`, oldest first.
+    pub fn compute(&self, instrument_id: i64, interval: ChartInterval, bars: &[ChartData]) -> IndicatorSeries {
+        // Bars without a close are left out of every window and get no value.
+        let with_close: Vec<(usize, f64)> = bars
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bar)| bar.close_price.as_ref().map(|close| (i, close.to_f64())))
+            .collect();
+        let closes: Vec<f64> = with_close.iter().map(|(_, close)| *close).collect();
+
+        let computed: Vec<(&'static str, Series)> = match *self {
+            Indicator::Sma { period } => vec![("sma", sma(&closes, period))],
+            Indicator::Ema { period } => vec![("ema", ema(&closes, period))],
+            Indicator::Rsi { period } => vec![("rsi", rsi(&closes, period))],
+            Indicator::Macd { fast, slow, signal } => {
+                let (macd, signal, histogram) = macd(&closes, fast, slow, signal);
+                vec![("macd", macd), ("signal", signal), ("histogram", histogram)]
+            },
+            Indicator::Bollinger { period, std_dev } => {
+                let (upper, middle, lower) = bollinger(&closes, period, std_dev);
+                vec![("upper", upper), ("middle", middle), ("lower", lower)]
+            },
+        };
+
+        let series = computed
+            .into_iter()
+            .map(|(name, values)| {
+                let mut aligned = vec![None; bars.len()];
+                for ((i, _), value) in with_close.iter().zip(values) {
+                    aligned[*i] = value;
+                }
+                (name, aligned)
+            })
+            .collect();
+
+        IndicatorSeries {
+            instrument_id,
+            interval,
+            indicator: *self,
+            timestamps: bars.iter().map(|bar| bar.timestamp).collect(),
+            series,
+        }
+    }
+}
+
+impl IndicatorSeries {
+    /// Drops the first `count` entries, which were computed only to warm the indicator up.
+    pub fn skip(mut self, count: usize) -> Self {
+        let count = count.min(self.timestamps.len());
+        self.timestamps.drain(..count);
+        for values in self.series.values_mut() {
+            values.drain(..count);
+        }
+        self
+    }
+}
+
+/// Simple moving average; the first `period - 1` values are `None`.
+pub fn sma(values: &[f64], period: usize) -> Series {
+    let mut out = vec![None; values.len()];
+    let mut sum = 0.0;
+    for (i, value) in values.iter().enumerate() {
+        sum += value;
+        if i >= period {
+            sum -= values[i - period];
+        }
+        if i + 1 >= period {
+            out[i] = Some(sum / period as f64);
+        }
+    }
+    out
+}
+
+/// Exponential moving average with smoothing `2 / (period + 1)`, seeded with the SMA of the first `period` values.
+pub fn ema(values: &[f64], period: usize) -> Series {
+    let values: Series = values.iter().copied().map(Some).collect();
+    ema_of(&values, period)
+}
+
+/// `ema` over a series that may start with `None`s, as the MACD line does.
+fn ema_of(values: &[Option<f64>], period: usize) -> Series {
+    let mut out = vec![None; values.len()];
+    let Some(start) = values.iter().position(Option::is_some) else {
+        return out;
+    };
+    let seed_end = start + period;
+    if seed_end > values.len() {
+        return out;
+    }
+
+    let alpha = 2.0 / (period as f64 + 1.0);
+    let mut current = values[start..seed_end].iter().flatten().sum::<f64>() / period as f64;
+    out[seed_end - 1] = Some(current);
+    for i in seed_end..values.len() {
+        if let Some(value) = values[i] {
+            current = alpha * value + (1.0 - alpha) * current;
+            out[i] = Some(current);
+        }
+    }
+    out
+}
+
+/// Wilder's RSI: average gains and losses start as plain means over the first `period` changes,
+/// then are smoothed by `1 / period`. A window without losses is 100, and one without any movement 50.
+pub fn rsi(values: &[f64], period: usize) -> Series {
+    let mut out = vec![None; values.len()];
+    if values.len() <= period {
+        return out;
+    }
+
+    let change = |i: usize| values[i] - values[i - 1];
+    let (mut gain, mut loss) = (1..=period).fold((0.0, 0.0), |(gain, loss), i| {
+        let change = change(i);
+        (gain + change.max(0.0), loss + (-change).max(0.0))
+    });
+    gain /= period as f64;
+    loss /= period as f64;
+    out[period] = Some(rsi_value(gain, loss));
+
+    for (i, value) in out.iter_mut().enumerate().skip(period + 1) {
+        let change = change(i);
+        gain = (gain * (period - 1) as f64 + change.max(0.0)) / period as f64;
+        loss = (loss * (period - 1) as f64 + (-change).max(0.0)) / period as f64;
+        *value = Some(rsi_value(gain, loss));
+    }
+    out
+}
+
+fn rsi_value(gain: f64, loss: f64) -> f64 {
+    if loss == 0.0 {
+        return if gain == 0.0 { 50.0 } else { 100.0 };
+    }
+    100.0 - 100.0 / (1.0 + gain / loss)
+}
+
+/// The MACD line (fast EMA minus slow EMA), its signal line (an EMA of the MACD line) and their difference.
+pub fn macd(values: &[f64], fast: usize, slow: usize, signal: usize) -> (Series, Series, Series) {
+    let fast = ema(values, fast);
+    let slow = ema(values, slow);
+    let line: Series = fast
+        .iter()
+        .zip(&slow)
+        .map(|(fast, slow)| Some((*fast)? - (*slow)?))
+        .collect();
+    let signal = ema_of(&line, signal);
+    let histogram = line
+        .iter()
+        .zip(&signal)
+        .map(|(line, signal)| Some((*line)? - (*signal)?))
+        .collect();
+    (line, signal, histogram)
+}
+
+/// Upper band, middle band (the SMA) and lower band.
+pub fn bollinger(values: &[f64], period: usize, std_dev: f64) -> (Series, Series, Series) {
+    let middle = sma(values, period);
+    let mut upper = vec![None; values.len()];
+    let mut lower = vec![None; values.len()];
+    for (i, mean) in middle.iter().enumerate() {
+        let Some(mean) = mean else {
+            continue;
+        };
+        let window = &values[i + 1 - period..=i];
+        let variance = window.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / period as f64;
+        let width = std_dev * variance.sqrt();
+        upper[i] = Some(mean + width);
+        lower[i] = Some(mean - width);
+    }
+    (upper, middle, lower)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::decimal::Decimal;
+
+    /// Sample closes from StockCharts' moving average article.
+    const MA_CLOSES: [f64; 30] = [
+        22.27, 22.19, 22.08, 22.17, 22.18, 22.13, 22.23, 22.43, 22.24, 22.29,
+        22.15, 22.39, 22.38, 22.61, 23.36, 24.05, 23.75, 23.83, 23.95, 23.63,
+        23.82, 23.87, 23.65, 23.19, 23.10, 23.33, 22.68, 23.10, 22.40, 22.17,
+    ];
+
+    /// Sample closes from StockCharts' RSI article.
+    const RSI_CLOSES: [f64; 33] = [
+        44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08,
+        45.89, 46.03, 45.61, 46.28, 46.28, 46.00, 46.03, 46.41, 46.22, 45.64,
+        46.21, 46.25, 45.71, 46.45, 45.78, 45.35, 44.03, 44.18, 44.22, 44.57,
+        43.42, 42.66, 43.13,
+    ];
+
+    fn assert_series(actual: &[Option<f64>], leading_nones: usize, expected: &[f64], tolerance: f64) {
+        assert!(actual[..leading_nones].iter().all(Option::is_none), "expected {} leading nulls in {:?}", leading_nones, actual);
+        let values: Vec<f64> = actual[leading_nones..].iter().map(|v| v.expect("value after warm-up")).collect();
+        assert_eq!(values.len(), expected.len());
+        for (i, (value, expected)) in values.iter().zip(expected).enumerate() {
+            assert!((value - expected).abs() <= tolerance, "value {} is {}, expected {}", i + leading_nones, value, expected);
+        }
+    }
+
+    #[test]
+    fn sma_matches_reference() {
+        let expected = [
+            22.221, 22.209, 22.229, 22.259, 22.303, 22.421, 22.613, 22.765, 22.905, 23.076, 23.210,
+            23.377, 23.525, 23.652, 23.710, 23.684, 23.612, 23.505, 23.432, 23.277, 23.131,
+        ];
+        assert_series(&sma(&MA_CLOSES, 10), 9, &expected, 1e-9);
+    }
+
+    #[test]
+    fn ema_matches_reference() {
+        let expected = [
+            22.22, 22.21, 22.24, 22.27, 22.33, 22.52, 22.80, 22.97, 23.13, 23.28, 23.34,
+            23.43, 23.51, 23.53, 23.47, 23.40, 23.39, 23.26, 23.23, 23.08, 22.92,
+        ];
+        assert_series(&ema(&MA_CLOSES, 10), 9, &expected, 0.005);
+    }
+
+    #[test]
+    fn rsi_matches_reference() {
+        // StockCharts rounds its intermediate averages and prints 70.53 first; these are unrounded.
+        let expected = [
+            70.46, 66.25, 66.48, 69.35, 66.29, 57.92, 62.88, 63.21, 56.01, 62.34,
+            54.67, 50.39, 40.02, 41.49, 41.90, 45.50, 37.32, 33.09, 37.79,
+        ];
+        assert_series(&rsi(&RSI_CLOSES, 14), 14, &expected, 0.005);
+    }
+
+    #[test]
+    fn rsi_without_losses_or_movement() {
+        assert_eq!(rsi(&[1.0, 2.0, 3.0], 2)[2], Some(100.0));
+        assert_eq!(rsi(&[1.0, 1.0, 1.0], 2)[2], Some(50.0));
+    }
+
+    #[test]
+    fn macd_line_matches_reference() {
+        let (line, signal, histogram) = macd(&RSI_CLOSES, 12, 26, 9);
+        let expected = [0.3067, 0.1394, 0.0187, -0.0729, -0.1159, -0.2400, -0.3951, -0.4747];
+        assert_series(&line, 25, &expected, 0.00005);
+        // Nine MACD values are needed to seed the signal line, and there are only eight.
+        assert!(signal.iter().chain(&histogram).all(Option::is_none));
+    }
+
+    #[test]
+    fn macd_of_a_ramp_is_the_lag_difference() {
+        // On a straight line every EMA lags by (period - 1) / 2, so MACD(12, 26) is exactly 7.
+        let ramp: Vec<f64> = (0..60).map(f64::from).collect();
+        let (line, signal, histogram) = macd(&ramp, 12, 26, 9);
+        assert_series(&line, 25, &[7.0; 35], 1e-9);
+        assert_series(&signal, 33, &[7.0; 27], 1e-9);
+        assert_series(&histogram, 33, &[0.0; 27], 1e-9);
+    }
+
+    #[test]
+    fn bollinger_uses_population_standard_deviation() {
+        let (upper, middle, lower) = bollinger(&[1.0, 2.0, 3.0, 4.0, 5.0], 5, 2.0);
+        let width = 2.0 * 2.0_f64.sqrt();
+        assert_series(&middle, 4, &[3.0], 1e-12);
+        assert_series(&upper, 4, &[3.0 + width], 1e-12);
+        assert_series(&lower, 4, &[3.0 - width], 1e-12);
+
+        let (upper, middle, lower) = bollinger(&MA_CLOSES[..20], 10, 2.0);
+        assert_eq!(middle[19].map(|v| (v * 1e4).round() / 1e4), Some(23.21));
+        assert_eq!(upper[19].map(|v| (v * 1e4).round() / 1e4), Some(24.6204));
+        assert_eq!(lower[19].map(|v| (v * 1e4).round() / 1e4), Some(21.7996));
+    }
+
+    #[test]
+    fn short_series_have_no_values() {
+        assert!(sma(&[1.0, 2.0], 3).iter().all(Option::is_none));
+        assert!(ema(&[1.0, 2.0], 3).iter().all(Option::is_none));
+        assert!(rsi(&[1.0, 2.0, 3.0], 3).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn compute_aligns_series_with_bars_and_skips_missing_closes() {
+        let bar = |minute: u32, close: Option<i64>| ChartData {
+            chart_data_id: i64::from(minute),
+            instrument_id: 1,
+            open_price: None,
+            close_price: close.map(Decimal::from),
+            high_price: None,
+            low_price: None,
+            volume: None,
+            timestamp: chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, minute, 0).unwrap(),
+        };
+        let bars = [bar(0, Some(1)), bar(1, None), bar(2, Some(3)), bar(3, Some(5))];
+
+        let result = Indicator::Sma { period: 2 }.compute(1, ChartInterval::Min1, &bars);
+        assert_eq!(result.timestamps, bars.iter().map(|b| b.timestamp).collect::<Vec<_>>());
+        assert_eq!(result.series["sma"], vec![None, None, Some(2.0), Some(4.0)]);
+
+        let trimmed = result.skip(2);
+        assert_eq!(trimmed.timestamps, vec![bars[2].timestamp, bars[3].timestamp]);
+        assert_eq!(trimmed.series["sma"], vec![Some(2.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn warmup_covers_every_window() {
+        assert_eq!(Indicator::Sma { period: 20 }.warmup_bars(), 20);
+        assert_eq!(Indicator::Bollinger { period: 20, std_dev: 2.0 }.warmup_bars(), 20);
+        assert!(Indicator::Rsi { period: 14 }.warmup_bars() > 14);
+        assert!(Indicator::Macd { fast: 12, slow: 26, signal: 9 }.warmup_bars() >= 26 + 9);
+    }
+
+    #[test]
+    fn resolve_fills_defaults_and_rejects_bad_parameters() {
+        let query = |indicator, fast, slow| IndicatorQuery {
+            indicator,
+            interval: None,
+            from: None,
+            to: None,
+            limit: None,
+            period: None,
+            fast,
+            slow,
+            signal: None,
+            std_dev: None,
+        };
+        assert_eq!(query(IndicatorName::Rsi, None, None).resolve(), Ok(Indicator::Rsi { period: 14 }));
+        assert_eq!(query(IndicatorName::Macd, None, None).resolve(), Ok(Indicator::Macd { fast: 12, slow: 26, signal: 9 }));
+        assert!(query(IndicatorName::Macd, Some(26), Some(12)).resolve().is_err());
+        assert!(IndicatorQuery { period: Some(0), ..query(IndicatorName::Sma, None, None) }.resolve().is_err());
+    }
+}
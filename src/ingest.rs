@@ -0,0 +1,74 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::PublisherConfig;
+use crate::feed::{Feed, FeedPayload};
+use crate::models::instrument::{InstrumentUpdate, UpdatePayload};
+use crate::repository::repository::{MarketDataRepository, RepositoryError};
+use crate::validation::{Rejection, UpdateValidator};
+
+#[derive(Debug)]
+pub enum IngestError {
+    /// The payload's `client_id` is not the publisher the API key belongs to.
+    WrongClient { authenticated: String, claimed: String },
+    Rejected(Rejection),
+    Repository(RepositoryError),
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::WrongClient { authenticated, claimed } => {
+                write!(f, "authenticated as {} but the payload claims client_id {}", authenticated, claimed)
+            },
+            IngestError::Rejected(rejection) => write!(f, "update rejected: {}", rejection),
+            IngestError::Repository(e) => write!(f, "could not store update: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+/// Entry point for price updates from publishers, over REST or WebSocket.
+/// Updates are validated, then persisted to `market_data`, then broadcast.
+pub struct Ingestor {
+    publishers: Vec<PublisherConfig>,
+    repository: Arc<dyn MarketDataRepository>,
+    validator: Arc<UpdateValidator>,
+    feed: Arc<Feed>,
+}
+
+impl Ingestor {
+    pub fn new(publishers: Vec<PublisherConfig>, repository: Arc<dyn MarketDataRepository>, validator: Arc<UpdateValidator>, feed: Arc<Feed>) -> Self {
+        Ingestor { publishers, repository, validator, feed }
+    }
+
+    /// The client ID owning `api_key`, if it is a configured publisher key.
+    pub fn authenticate(&self, api_key: &str) -> Option<&str> {
+        self.publishers
+            .iter()
+            .find(|publisher| constant_time_eq(publisher.api_key.as_bytes(), api_key.as_bytes()))
+            .map(|publisher| publisher.client_id.as_str())
+    }
+
+    /// Applies an update from the publisher `client_id`, as returned by `authenticate`.
+    pub async fn ingest(&self, client_id: &str, payload: UpdatePayload) -> Result<InstrumentUpdate, IngestError> {
+        if payload.client_id != client_id {
+            return Err(IngestError::WrongClient { authenticated: client_id.to_string(), claimed: payload.client_id });
+        }
+
+        let update = self.validator.validate(payload.instrument).await.map_err(IngestError::Rejected)?;
+        self.repository.update_instrument(&update).await.map_err(IngestError::Repository)?;
+
+        // Postgres broadcasts the stored row through NOTIFY; publishing here as well would send it twice.
+        if !self.repository.notifies_updates() {
+            self.feed.publish(FeedPayload::Price(update.clone()));
+        }
+        Ok(update)
+    }
+}
+
+/// Compares without an early exit, so response timing does not reveal how much of a key matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -1,4 +1,14 @@
+#![allow(non_snake_case)]
 pub mod repository;
 pub mod models;
 pub mod api;
-pub mod listener;
\ No newline at end of file
+pub mod listener;
+pub mod feed;
+pub mod validation;
+pub mod websocket;
+pub mod config;
+pub mod error;
+pub mod ingest;
+pub mod shutdown;
+pub mod bars;
+pub mod indicators;
@@ -1,45 +1,170 @@
-use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use futures::{stream, StreamExt};
-use futures::{FutureExt, TryStreamExt};
-use tokio_postgres::NoTls;
-use tokio::sync::broadcast::Sender;
-use dotenv::dotenv;
-pub async fn listen_for_price_changes(bcast_tx: Sender<String>) {
-    dotenv().ok();
-    let connection_string = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
-
-    let (client, mut connection) = tokio_postgres::connect(
-        &connection_string,
-        NoTls,
-    )
-    .await
-    .unwrap();
+use serde::Serialize;
+use tokio::time::Instant;
+use tokio_postgres::{AsyncMessage, Error, NoTls};
+use crate::feed::{Feed, FeedPayload};
+use crate::shutdown::Shutdown;
+use crate::validation::UpdateValidator;
 
-    let (tx, mut rx) = futures_channel::mpsc::unbounded();
-    let stream = stream::poll_fn(move |cx| connection.poll_message(cx)).map_err(|e| panic!("{}", e));
-    let connection = stream.forward(tx).map(|r| r.unwrap());
-    tokio::spawn(connection);
-
-    tokio::spawn(async move {
-        while let Some(message) = rx.next().await {
-            match message {
-                tokio_postgres::AsyncMessage::Notification(n) => {
-                    if let Err(e) = bcast_tx.send(n.payload().to_string()) {
-                        eprintln!("Failed to send notification to clients: {:?}", e);
-                    }
-                },
-                _ => {}
-            }
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Consecutive failed attempts after which the listener reports itself down. It keeps retrying regardless.
+const DOWN_AFTER_FAILURES: u32 = 5;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerState {
+    Connected,
+    Reconnecting,
+    /// Not connected yet, failing repeatedly, or stopped for shutdown.
+    Down,
+    /// No database URL is configured, so no listener runs.
+    Disabled,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ListenerHealth {
+    pub state: ListenerState,
+    pub reconnects: u64,
+    pub last_error: Option<String>,
+}
+
+/// Shared view of the NOTIFY listener's connection, read by the health endpoint.
+pub struct ListenerStatus {
+    health: RwLock<ListenerHealth>,
+}
+
+impl Default for ListenerStatus {
+    fn default() -> Self {
+        ListenerStatus {
+            health: RwLock::new(ListenerHealth { state: ListenerState::Down, reconnects: 0, last_error: None }),
         }
-    });
+    }
+}
+
+impl ListenerStatus {
+    /// Status for a server running without a listener.
+    pub fn disabled() -> Self {
+        ListenerStatus {
+            health: RwLock::new(ListenerHealth { state: ListenerState::Disabled, reconnects: 0, last_error: None }),
+        }
+    }
+
+    pub fn health(&self) -> ListenerHealth {
+        self.health.read().unwrap().clone()
+    }
+
+    /// Whether live updates are flowing, or were never expected to.
+    pub fn is_healthy(&self) -> bool {
+        matches!(self.health().state, ListenerState::Connected | ListenerState::Disabled)
+    }
+
+    fn connected(&self) {
+        self.health.write().unwrap().state = ListenerState::Connected;
+    }
+
+    /// Records the `failures`th consecutive failed attempt.
+    fn reconnecting(&self, error: String, failures: u32) {
+        let mut health = self.health.write().unwrap();
+        health.state = if failures >= DOWN_AFTER_FAILURES { ListenerState::Down } else { ListenerState::Reconnecting };
+        health.reconnects += 1;
+        health.last_error = Some(error);
+    }
 
-    if let Err(e) = client.execute("LISTEN last_price_change", &[]).await {
-        eprintln!("Error starting to listen: {}", e);
-        return;
+    fn stopped(&self) {
+        self.health.write().unwrap().state = ListenerState::Down;
     }
+}
+
+/// Keeps a `LISTEN last_price_change` session open, reconnecting with exponential backoff
+/// whenever the connection drops. Each recovery publishes a gap event so clients can resync.
+/// Returns once `shutdown` is triggered, after unlistening.
+pub async fn listen_for_price_changes(connection_string: String, feed: Arc<Feed>, status: Arc<ListenerStatus>, validator: Arc<UpdateValidator>, shutdown: Arc<Shutdown>) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut lost_at: Option<Instant> = None;
+    let mut failures = 0;
 
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+        let on_listening = || {
+            status.connected();
+            backoff = INITIAL_BACKOFF;
+            failures = 0;
+            if let Some(lost_at) = lost_at.take() {
+                let down_for_ms = lost_at.elapsed().as_millis() as u64;
+                println!("Price listener reconnected after {}ms", down_for_ms);
+                feed.publish(FeedPayload::Gap { down_for_ms });
+            }
+        };
+
+        let result = listen(&connection_string, &feed, &validator, &shutdown, on_listening).await;
+        if shutdown.is_triggered() {
+            status.stopped();
+            println!("Price listener stopped");
+            return;
+        }
+        let error = match result {
+            Ok(()) => "connection closed".to_string(),
+            Err(e) => e.to_string(),
+        };
+        eprintln!("Price listener lost its connection: {}, retrying in {:?}", error, backoff);
+        failures += 1;
+        status.reconnecting(error, failures);
+        lost_at.get_or_insert_with(Instant::now);
+
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {},
+            _ = shutdown.wait() => {
+                status.stopped();
+                return;
+            },
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
     }
-}
\ No newline at end of file
+}
+
+/// Runs a single listener session until the connection closes or fails, or shutdown is triggered.
+/// Only notifications that parse and validate are published; the validator logs and counts the rest.
+async fn listen(connection_string: &str, feed: &Feed, validator: &UpdateValidator, shutdown: &Shutdown, on_listening: impl FnOnce()) -> Result<(), Error> {
+    let (client, mut connection) = tokio_postgres::connect(connection_string, NoTls).await?;
+
+    // The connection has to be polled for LISTEN to complete, so drive it from its own task
+    // and hand notifications (or the error that ends the session) back over a channel.
+    let (tx, mut rx) = futures_channel::mpsc::unbounded();
+    let driver = tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            let failed = message.is_err();
+            if tx.unbounded_send(message).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let result = async {
+        client.batch_execute("LISTEN last_price_change").await?;
+        on_listening();
+
+        loop {
+            let message = tokio::select! {
+                message = rx.next() => message,
+                _ = shutdown.wait() => {
+                    client.batch_execute("UNLISTEN *").await?;
+                    return Ok(());
+                },
+            };
+            let Some(message) = message else {
+                return Ok(());
+            };
+            if let AsyncMessage::Notification(n) = message? {
+                if let Ok(update) = validator.parse(n.payload()).await {
+                    feed.publish(FeedPayload::Price(update));
+                }
+            }
+        }
+    }.await;
+
+    driver.abort();
+    result
+}
@@ -1,136 +1,156 @@
-use FEED_DATA::listener::listen_for_price_changes;
-use FEED_DATA::models::instrument::UpdatePayload;
-use futures_util::sink::SinkExt;
-use futures_util::stream::StreamExt;
-use std::error::Error;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::broadcast::{channel, Sender};
-use tokio_websockets::{Message, ServerBuilder, WebSocketStream};
-use FEED_DATA::{repository, api::api};
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder, Result};
+#![allow(non_snake_case)]
+use FEED_DATA::listener::{listen_for_price_changes, ListenerHealth, ListenerStatus};
+use FEED_DATA::websocket::start_websocket_server;
+use FEED_DATA::feed::Feed;
+use FEED_DATA::bars::spawn_bar_builder;
+use FEED_DATA::ingest::Ingestor;
+use FEED_DATA::validation::{UpdateValidator, ValidationStats};
+use FEED_DATA::api::{api, number_format::number_format, request_id::request_id};
+use FEED_DATA::error::ApiError;
+use FEED_DATA::repository::{database::Database, memory::InMemoryRepository, repository::MarketDataRepository};
+use FEED_DATA::config::{Backend, Config};
+use FEED_DATA::shutdown::{self, Shutdown};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use actix_web::{get, middleware::from_fn, web, App, HttpResponse, HttpServer, Responder};
 use serde::Serialize;
 use actix_cors::Cors;
-use tokio::sync::broadcast;
-use lazy_static::lazy_static;
-use local_ip_address::local_ip;
-
-lazy_static! {
-    static ref TX: broadcast::Sender<String> = broadcast::channel(10).0;
-}
 
 #[derive(Serialize)]
-pub struct Response {
+pub struct HealthResponse {
     pub message: String,
+    pub listener: ListenerHealth,
+    pub validation: ValidationStats,
 }
+
 #[get("/health")]
-async fn healthcheck() -> impl Responder {
-    let response = Response {
-        message: "Everything is working fine".to_string(),
-    };
-    HttpResponse::Ok().json(response)
+async fn healthcheck(listener: web::Data<ListenerStatus>, validator: web::Data<UpdateValidator>) -> impl Responder {
+    let healthy = listener.is_healthy();
+    let listener = listener.health();
+    let validation = validator.stats();
+    if healthy {
+        HttpResponse::Ok().json(HealthResponse {
+            message: "Everything is working fine".to_string(),
+            listener,
+            validation,
+        })
+    } else {
+        HttpResponse::ServiceUnavailable().json(HealthResponse {
+            message: "Live price updates are unavailable".to_string(),
+            listener,
+            validation,
+        })
+    }
 }
 
-async fn not_found() -> Result<HttpResponse> {
-    let response = Response {
-        message: "Resource not found".to_string(),
-    };
-    Ok(HttpResponse::NotFound().json(response))
+async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound("Resource not found".to_string()))
 }
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let addr: String;
-    let port = "8080";
-    let feed_data = repository::database::Database::new();
-    
-    match local_ip() {
-        Ok(ip) => {
-            let ip_str = ip.to_string(); 
-            addr = ip_str.clone() +":"+ port;    
-        },
-        Err(_e) => {
-            addr = "192.168.0.101:8080".to_string();  
-        },
-    }
+    let config = Config::load().map_err(std::io::Error::other)?;
+    println!("Effective configuration:\n{}", config.redacted());
+
+    let shutdown = Arc::new(Shutdown::new());
+    let feed = Arc::new(Feed::new(config.feed.channel_capacity, config.feed.replay_capacity));
+    let (feed_data, cache_sync) = load_repository(&config, feed.clone(), shutdown.clone()).await?;
+    let addr = config.http.socket_addr();
 
-    let (bcast_tx, _bcast_rx) = channel(16);
-    let app_data = web::Data::new(feed_data);
+    let app_data = web::Data::from(feed_data.clone());
+    let listener_status = Arc::new(match config.database.url {
+        Some(_) => ListenerStatus::default(),
+        None => ListenerStatus::disabled(),
+    });
+    let status_data = web::Data::from(listener_status.clone());
+    let validator = Arc::new(UpdateValidator::new(feed_data.clone()));
+    let validator_data = web::Data::from(validator.clone());
+    if config.publishers.is_empty() {
+        println!("No publishers configured, price updates from clients will be refused");
+    }
+    let ingestor = Arc::new(Ingestor::new(config.publishers.clone(), feed_data.clone(), validator.clone(), feed.clone()));
+    let ingestor_data = web::Data::from(ingestor.clone());
+    let feed_stats_data = web::Data::from(feed.clone());
+    let shutdown_data = web::Data::from(shutdown.clone());
     let server = HttpServer::new(move || {
         App::new()
         .app_data(app_data.clone())
+        .app_data(status_data.clone())
+        .app_data(validator_data.clone())
+        .app_data(ingestor_data.clone())
+        .app_data(feed_stats_data.clone())
+        .app_data(shutdown_data.clone())
         .configure(api::config)
         .service(healthcheck)
         .default_service(web::route().to(not_found))
+        .wrap(from_fn(number_format))
+        .wrap(from_fn(request_id))
         .wrap(actix_web::middleware::Logger::default())
         .wrap(Cors::permissive())
     })
-    .bind(addr.clone())?;
+    // Signals are handled below, so the WebSocket server and listener stop together with HTTP.
+    .disable_signals()
+    .shutdown_timeout(config.shutdown.timeout_secs)
+    .bind(addr)?
+    .run();
+
+    println!("Feed server running at http://{}", addr);
+    let http = server.handle();
+    tokio::spawn(server);
+
+    let bar_builder = spawn_bar_builder(feed.clone(), feed_data.clone(), config.bars.persist, shutdown.clone());
+    let websocket = tokio::spawn(start_websocket_server(config.websocket.socket_addr(), feed.clone(), feed_data.clone(), ingestor.clone(), config.websocket.client_backlog, shutdown.clone()));
 
-    println!("Feed server running at http://{}", addr.clone());
-    tokio::spawn(server.run());
+    let listener = match &config.database.url {
+        Some(url) => Some(tokio::spawn(listen_for_price_changes(url.clone(), feed.clone(), listener_status.clone(), validator.clone(), shutdown.clone()))),
+        None => {
+            println!("No database URL configured, live price notifications disabled");
+            None
+        },
+    };
 
-    tokio::spawn(start_websocket_server(bcast_tx.clone()));
-    
-    tokio::spawn(listen_for_price_changes(bcast_tx.clone()));
+    shutdown::signal().await;
+    println!("Shutting down, allowing {:?} for connections to finish...", config.shutdown.timeout());
+    shutdown.trigger();
 
-    tokio::signal::ctrl_c().await.expect("Failed to wait for Ctrl+C");
-    println!("Shutting down...");
+    // Both servers stop accepting right away; `stop(true)` then waits for in-flight requests.
+    let drained = tokio::time::timeout(config.shutdown.timeout(), async {
+        let _ = tokio::join!(http.stop(true), websocket, bar_builder, join(listener), join(cache_sync));
+    }).await;
+    if drained.is_err() {
+        eprintln!("Shutdown deadline passed, dropping remaining connections");
+    }
 
+    // The database pool closes its connections once the last repository handle is dropped on return.
+    println!("Shutdown complete");
     Ok(())
 }
 
-async fn start_websocket_server(bcast_tx: Sender<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let addr: String;
-    match local_ip() {
-        Ok(ip) => {
-            let ip_str = ip.to_string(); 
-            addr = ip_str.clone() +":1092";        
-        },
-        Err(_e) => {
-            addr = "192.168.0.101:1092".to_string();  
-        },
-    }
-    let listener = TcpListener::bind(addr.clone()).await?;
-    println!("Price update listening on {:?}", addr.clone());
-    
-    loop {
-        let (socket, _) = listener.accept().await?;
-        let bcast_tx = bcast_tx.clone();
-        tokio::spawn(async move {
-            let ws_stream = ServerBuilder::new().accept(socket).await?;
-            handle_connection(ws_stream, bcast_tx).await
-        });
+async fn join(task: Option<JoinHandle<()>>) {
+    if let Some(task) = task {
+        let _ = task.await;
     }
 }
 
-
-async fn handle_connection(mut ws_stream: WebSocketStream<TcpStream>, bcast_tx: Sender<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
-
-    let mut bcast_rx = bcast_tx.subscribe();
-
-    loop {
-        tokio::select! {
-            incoming = ws_stream.next() => {
-                match incoming {
-                    Some(Ok(msg)) => {
-                        if let Some(data) = msg.as_text() {
-                            let payload: UpdatePayload = serde_json::from_str(data).unwrap();
-                            // if payload.client_id == "OMS_SERVER" {
-                                println!("from not {:?}", payload);
-                                let playload_json = serde_json::to_string(&payload.instrument)?;
-                                bcast_tx.send(playload_json.to_string())?;
-                            // }
-                        }
-                    }
-                    Some(Err(err)) => return Err(err.into()),
-                    None => return Ok(()),
-                }
-            }
-            msg = bcast_rx.recv() => {
-                println!("from not {:?}", msg);
-                ws_stream.send(Message::text(msg?)).await?;
-            }
-        }
+/// Builds the configured market data backend: Postgres (with its instrument cache kept in sync from `feed`
+/// by the returned task), or the in-memory store seeded from a JSON fixture.
+async fn load_repository(config: &Config, feed: Arc<Feed>, shutdown: Arc<Shutdown>) -> std::io::Result<(Arc<dyn MarketDataRepository>, Option<JoinHandle<()>>)> {
+    match config.backend.kind {
+        Backend::Postgres => {
+            // Config::validate guarantees a URL for this backend.
+            let url = config.database.url.as_deref().unwrap_or_default();
+            let database = Database::new(url, config.database.pool_config())
+                .await
+                .map_err(std::io::Error::other)?;
+            let database = Arc::new(database);
+            let cache_sync = database.clone().sync_cache(feed, config.database.cache_refresh(), shutdown);
+            Ok((database, Some(cache_sync)))
+        },
+        Backend::Memory => {
+            let fixture = &config.backend.fixture;
+            let repository = InMemoryRepository::from_fixture(fixture)
+                .map_err(std::io::Error::other)?;
+            println!("Serving market data from fixture {}", fixture.display());
+            Ok((Arc::new(repository), None))
+        },
     }
 }
-
-
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use bigdecimal::{BigDecimal, Signed, Zero};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// How decimals are written to JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberFormat {
+    /// Strings holding the exact value, e.g. `"227.50"`.
+    #[default]
+    Exact,
+    /// Plain JSON numbers, as the API returned before decimals were exact. May lose precision.
+    Float,
+}
+
+impl NumberFormat {
+    /// Parses the value of the `numbers` query parameter.
+    pub fn from_param(value: &str) -> Option<NumberFormat> {
+        match value {
+            "exact" => Some(NumberFormat::Exact),
+            "float" => Some(NumberFormat::Float),
+            _ => None,
+        }
+    }
+}
+
+tokio::task_local! {
+    static NUMBER_FORMAT: NumberFormat;
+}
+
+/// Runs `f` with every `Decimal` it serializes written in `format`.
+pub async fn with_number_format<F: Future>(format: NumberFormat, f: F) -> F::Output {
+    NUMBER_FORMAT.scope(format, f).await
+}
+
+/// The format set by `with_number_format` for the running task, or the default outside of one.
+pub fn number_format() -> NumberFormat {
+    NUMBER_FORMAT.try_with(|format| *format).unwrap_or_default()
+}
+
+/// An exact decimal, as stored in Postgres `numeric` columns.
+/// Deserializes from a string or a JSON number. Numbers pass through `f64` on the way in,
+/// so clients needing more than 15 significant digits should send strings.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Decimal(pub BigDecimal);
+
+impl Decimal {
+    pub fn zero() -> Self {
+        Decimal(BigDecimal::zero())
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0.is_positive()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.is_negative()
+    }
+
+    pub fn abs(&self) -> Decimal {
+        Decimal(self.0.abs())
+    }
+
+    /// Nearest `f64`, for plotting and the legacy float output. Parsed from the decimal string,
+    /// since `BigDecimal::to_f64` scales by a power of ten and can land one bit off.
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// Parses a raw JSON number or string, keeping every digit of a number.
+    pub fn from_json(raw: &str) -> Option<Decimal> {
+        let raw = raw.trim();
+        let unquoted = raw.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(raw);
+        unquoted.parse().ok()
+    }
+
+    /// Exact conversion from the shortest decimal form of `value`. Fails for NaN and infinities.
+    pub fn from_f64(value: f64) -> Option<Decimal> {
+        if !value.is_finite() {
+            return None;
+        }
+        value.to_string().parse().ok()
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Decimal(BigDecimal::from(value))
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = bigdecimal::ParseBigDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BigDecimal::from_str(s.trim()).map(Decimal)
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::ops::Add for &Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: &Decimal) -> Decimal {
+        Decimal(&self.0 + &other.0)
+    }
+}
+
+impl std::ops::Sub for &Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: &Decimal) -> Decimal {
+        Decimal(&self.0 - &other.0)
+    }
+}
+
+impl std::ops::Mul for &Decimal {
+    type Output = Decimal;
+
+    fn mul(self, other: &Decimal) -> Decimal {
+        Decimal(&self.0 * &other.0)
+    }
+}
+
+impl std::ops::Div for &Decimal {
+    type Output = Decimal;
+
+    /// Panics if `other` is zero.
+    fn div(self, other: &Decimal) -> Decimal {
+        Decimal(&self.0 / &other.0)
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match number_format() {
+            NumberFormat::Exact => serializer.collect_str(&self.0),
+            NumberFormat::Float => serializer.serialize_f64(self.to_f64()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalVisitor;
+
+        impl de::Visitor<'_> for DecimalVisitor {
+            type Value = Decimal;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a decimal number or a string holding one")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimal, E> {
+                value.parse().map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimal, E> {
+                Ok(Decimal::from(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimal, E> {
+                Ok(Decimal(BigDecimal::from(value)))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimal, E> {
+                Decimal::from_f64(value).ok_or_else(|| E::invalid_value(de::Unexpected::Float(value), &self))
+            }
+        }
+
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_json(value: &Decimal, format: NumberFormat) -> String {
+        NUMBER_FORMAT.sync_scope(format, || serde_json::to_string(value).unwrap())
+    }
+
+    #[test]
+    fn float_format_matches_the_f64_literal() {
+        for literal in ["577.8", "122.85", "226.1", "0.1", "227.52", "-2.27", "1e-7", "12345678901234567890.5"] {
+            let value: Decimal = literal.parse().unwrap();
+            let expected = serde_json::to_string(&literal.parse::<f64>().unwrap()).unwrap();
+            assert_eq!(to_json(&value, NumberFormat::Float), expected, "{}", literal);
+        }
+    }
+
+    #[test]
+    fn exact_format_keeps_every_digit() {
+        let value: Decimal = "227.50".parse().unwrap();
+        assert_eq!(to_json(&value, NumberFormat::Exact), "\"227.50\"");
+    }
+}
@@ -1,6 +1,8 @@
-use chrono::NaiveDateTime;
+use std::cmp::Ordering;
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, Timelike};
 use serde::{Deserialize, Serialize};
-use core_graphics::geometry::CGPoint;
+
+use crate::models::decimal::Decimal;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct InstrumentCode{
@@ -12,58 +14,109 @@ pub struct SparkPoint {
     pub y: f64,
 }
 
-impl From<CGPoint> for SparkPoint {
-    fn from(point: CGPoint) -> Self {
-        SparkPoint { x: point.x, y: point.y }
+#[cfg(all(feature = "cgpoint", target_vendor = "apple"))]
+mod cgpoint {
+    use core_graphics::geometry::CGPoint;
+    use super::SparkPoint;
+
+    impl From<CGPoint> for SparkPoint {
+        fn from(point: CGPoint) -> Self {
+            SparkPoint { x: point.x, y: point.y }
+        }
     }
-}
 
-impl From<SparkPoint> for CGPoint {
-    fn from(gram_point: SparkPoint) -> Self {
-        CGPoint::new(gram_point.x, gram_point.y)
+    impl From<SparkPoint> for CGPoint {
+        fn from(gram_point: SparkPoint) -> Self {
+            CGPoint::new(gram_point.x, gram_point.y)
+        }
     }
 }
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Prices are exact; columns that are NULL in `market_data` come back as `None`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Instrument{
     pub instrument_id: i64,
     pub code: String,
     pub symbol: String,
-    pub last_price: f64,
-    pub prev_price: f64,
-    pub change: f64,
-    pub volume: i64,
+    pub last_price: Option<Decimal>,
+    pub prev_price: Option<Decimal>,
+    pub change: Option<Decimal>,
+    pub volume: Option<i64>,
     pub spark: Vec<SparkPoint>
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct InstrumentUpdate{
     pub instrument_id: i32,
-    pub last_price: f64,
-    pub prev_price: f64,
-    pub change: f64
+    pub last_price: Decimal,
+    pub prev_price: Decimal,
+    pub change: Decimal
 }
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// Current state of an instrument with statistics from its history.
+/// The 24h fields are null, and `vol_24` zero, when it has not traded in the last 24h.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
 pub struct InstrumentDetail{
     pub instrument_id: i64,
-    // pub code: String,
-    // pub symbol: String,
-    // pub last_price: f64,
-    // pub prev_price: f64,
-	// pub change: f64,
-	// pub today_change: f64,
-	// pub day_7_change: f64,
-	// pub day_30_change: f64,
-	// pub day_90_change: f64,
-	// pub day_180_change: f64,
-	// pub year_1_change: f64,
-	// pub market_cap: f64,
-	pub vol_24 : f64,
-	pub high_24: f64,
-	pub low_24: f64,
-	// pub total_vol: f64
+    /// Filled from `market_data`, so fixture details may omit them.
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub symbol: String,
+    pub last_price: Option<Decimal>,
+    pub prev_price: Option<Decimal>,
+	pub change: Option<Decimal>,
+	/// Percent change since the last close before midnight (UTC).
+	pub today_change: Option<Decimal>,
+	/// Percent changes against the last close at least that long ago. Null when history is shorter.
+	pub day_7_change: Option<Decimal>,
+	pub day_30_change: Option<Decimal>,
+	pub day_90_change: Option<Decimal>,
+	pub day_180_change: Option<Decimal>,
+	pub year_1_change: Option<Decimal>,
+	pub high_52w: Option<Decimal>,
+	pub low_52w: Option<Decimal>,
+	/// Mean daily volume over the days traded in the last 30.
+	pub avg_volume: Option<Decimal>,
+	/// Volume-weighted average of the typical price (high + low + close) / 3 over the last 24h.
+	pub vwap: Option<Decimal>,
+	/// Last price times shares outstanding. Null when the share count is unknown.
+	pub market_cap: Option<Decimal>,
+	pub vol_24 : Option<Decimal>,
+	pub high_24: Option<Decimal>,
+	pub low_24: Option<Decimal>,
+	/// Volume over all stored history.
+	pub total_vol: Option<Decimal>,
+}
+
+
+
+/// Identifies an instrument by numeric ID or by its code/symbol.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum InstrumentKey {
+    Id(i64),
+    Symbol(String),
 }
 
+impl InstrumentKey {
+    /// An ID read as a code or symbol instead, for codes that are all digits such as `7203`.
+    pub fn as_symbol(&self) -> Option<InstrumentKey> {
+        match self {
+            InstrumentKey::Id(id) => Some(InstrumentKey::Symbol(id.to_string())),
+            InstrumentKey::Symbol(_) => None,
+        }
+    }
+}
 
+impl From<&str> for InstrumentKey {
+    /// Reads a path segment: a plain number is an ID, anything else a code or symbol. Digits with a
+    /// leading zero, like `0700`, can only be a code. Lookups retry IDs that match nothing as codes.
+    fn from(raw: &str) -> Self {
+        match raw.parse::<i64>() {
+            Ok(id) if id.to_string() == raw => InstrumentKey::Id(id),
+            _ => InstrumentKey::Symbol(raw.to_string()),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct UpdatePayload {
@@ -75,21 +128,658 @@ pub struct UpdatePayload {
 pub struct ChartData {
     pub chart_data_id: i64,
     pub instrument_id: i64,
-    pub open_price: f64,
-    pub close_price: f64,
-    pub high_price: f64,
-    pub low_price: f64,
-    pub volume: f64,
+    pub open_price: Option<Decimal>,
+    pub close_price: Option<Decimal>,
+    pub high_price: Option<Decimal>,
+    pub low_price: Option<Decimal>,
+    pub volume: Option<Decimal>,
     pub timestamp: NaiveDateTime
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Serialized as the documented `interval` values; the variant names are still accepted on input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
 pub enum ChartInterval{
+    #[serde(rename = "1m", alias = "Min1")]
     Min1, 
+    #[serde(rename = "5m", alias = "Min5")]
     Min5,
+    #[serde(rename = "30m", alias = "Min30")]
     Min30, 
+    #[default]
+    #[serde(rename = "1h", alias = "Hour1")]
     Hour1,
+    #[serde(rename = "1d", alias = "Day1")]
     Day1,
+    #[serde(rename = "1M", alias = "Month1")]
     Month1
 }
 
+impl ChartInterval {
+    pub const ALL: [ChartInterval; 6] = [
+        ChartInterval::Min1,
+        ChartInterval::Min5,
+        ChartInterval::Min30,
+        ChartInterval::Hour1,
+        ChartInterval::Day1,
+        ChartInterval::Month1,
+    ];
+
+    /// SQL expression bucketing `market_data_chart.timestamp` into bars of this interval.
+    pub fn sql_bucket(&self) -> &'static str {
+        match self {
+            ChartInterval::Min1 => "date_trunc('minute', timestamp)",
+            ChartInterval::Min5 => "date_bin('5 minutes', timestamp, TIMESTAMP '2000-01-01')",
+            ChartInterval::Min30 => "date_bin('30 minutes', timestamp, TIMESTAMP '2000-01-01')",
+            ChartInterval::Hour1 => "date_trunc('hour', timestamp)",
+            ChartInterval::Day1 => "date_trunc('day', timestamp)",
+            ChartInterval::Month1 => "date_trunc('month', timestamp)",
+        }
+    }
+
+    /// Start of the bar containing `timestamp`, matching `sql_bucket`.
+    pub fn bucket_start(&self, timestamp: NaiveDateTime) -> NaiveDateTime {
+        let date = timestamp.date();
+        let floor_minutes = |step: u32| {
+            let minute = timestamp.minute() - timestamp.minute() % step;
+            date.and_hms_opt(timestamp.hour(), minute, 0).unwrap()
+        };
+        match self {
+            ChartInterval::Min1 => floor_minutes(1),
+            ChartInterval::Min5 => floor_minutes(5),
+            ChartInterval::Min30 => floor_minutes(30),
+            ChartInterval::Hour1 => date.and_hms_opt(timestamp.hour(), 0, 0).unwrap(),
+            ChartInterval::Day1 => date.and_hms_opt(0, 0, 0).unwrap(),
+            ChartInterval::Month1 => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        }
+    }
+
+    /// Start of the bar after the one starting at `start`.
+    pub fn bucket_end(&self, start: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            ChartInterval::Min1 => start + Duration::minutes(1),
+            ChartInterval::Min5 => start + Duration::minutes(5),
+            ChartInterval::Min30 => start + Duration::minutes(30),
+            ChartInterval::Hour1 => start + Duration::hours(1),
+            ChartInterval::Day1 => start + Duration::days(1),
+            ChartInterval::Month1 => start.checked_add_months(Months::new(1)).unwrap_or(NaiveDateTime::MAX),
+        }
+    }
+
+    /// How far back a chart reaches when the caller gives no `from`.
+    pub fn default_lookback(&self) -> Duration {
+        match self {
+            ChartInterval::Min1 => Duration::hours(6),
+            ChartInterval::Min5 => Duration::days(1),
+            ChartInterval::Min30 => Duration::days(7),
+            ChartInterval::Hour1 => Duration::days(1),
+            ChartInterval::Day1 => Duration::days(365),
+            ChartInterval::Month1 => Duration::days(365 * 5),
+        }
+    }
+}
+
+pub const DEFAULT_CHART_LIMIT: i64 = 500;
+pub const MAX_CHART_LIMIT: i64 = 5000;
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+pub const MAX_PAGE_LIMIT: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstrumentSort {
+    #[default]
+    InstrumentId,
+    LastPrice,
+    Change,
+    Volume,
+    Code,
+    Symbol,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Direction of an instrument's last change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeSign {
+    Up,
+    Down,
+    Flat,
+}
+
+impl InstrumentSort {
+    /// Column `market_data` is ordered by for this sort. Postgres sorts `NaN` above every number,
+    /// but it is read as a missing value, so it is ordered as NULL to agree with `compare`.
+    pub fn sql_column(&self) -> &'static str {
+        match self {
+            InstrumentSort::InstrumentId => "instrument_id",
+            InstrumentSort::LastPrice => "NULLIF(last_price, 'NaN')",
+            InstrumentSort::Change => "NULLIF(change, 'NaN')",
+            InstrumentSort::Volume => "volume",
+            InstrumentSort::Code => "code",
+            InstrumentSort::Symbol => "symbol",
+        }
+    }
+
+    /// Compares by this key in `order`, with missing values last either way, matching `NULLS LAST`.
+    pub fn compare(&self, a: &Instrument, b: &Instrument, order: SortOrder) -> Ordering {
+        fn nulls_last<T: Ord>(a: Option<T>, b: Option<T>, order: SortOrder) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => match order {
+                    SortOrder::Asc => a.cmp(&b),
+                    SortOrder::Desc => b.cmp(&a),
+                },
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+
+        match self {
+            InstrumentSort::InstrumentId => nulls_last(Some(a.instrument_id), Some(b.instrument_id), order),
+            InstrumentSort::LastPrice => nulls_last(a.last_price.as_ref(), b.last_price.as_ref(), order),
+            InstrumentSort::Change => nulls_last(a.change.as_ref(), b.change.as_ref(), order),
+            InstrumentSort::Volume => nulls_last(a.volume, b.volume, order),
+            InstrumentSort::Code => nulls_last(Some(&a.code), Some(&b.code), order),
+            InstrumentSort::Symbol => nulls_last(Some(&a.symbol), Some(&b.symbol), order),
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+impl ChangeSign {
+    /// SQL condition on `market_data.change`.
+    pub fn sql_condition(&self) -> &'static str {
+        match self {
+            ChangeSign::Up => "change > 0 AND change <> 'NaN'",
+            ChangeSign::Down => "change < 0",
+            ChangeSign::Flat => "change = 0",
+        }
+    }
+
+    pub fn matches(&self, change: &Decimal) -> bool {
+        match self {
+            ChangeSign::Up => change.is_positive(),
+            ChangeSign::Down => change.is_negative(),
+            ChangeSign::Flat => !change.is_positive() && !change.is_negative(),
+        }
+    }
+}
+
+/// Query parameters accepted by `/api/instruments`. Every field is optional.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct InstrumentQuery {
+    pub sort: Option<InstrumentSort>,
+    pub order: Option<SortOrder>,
+    pub limit: Option<i64>,
+    /// `next_cursor` from the previous page.
+    pub cursor: Option<String>,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub min_volume: Option<i64>,
+    pub change: Option<ChangeSign>,
+    pub spark: Option<bool>,
+}
+
+/// An instrument list request with defaults applied.
+/// Instruments with a NULL in a filtered column never match that filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstrumentListing {
+    pub sort: InstrumentSort,
+    pub order: SortOrder,
+    pub limit: i64,
+    pub offset: i64,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub min_volume: Option<i64>,
+    pub change: Option<ChangeSign>,
+    pub spark: bool,
+}
+
+/// One page of instruments. `next_cursor` is absent on the last page.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct InstrumentPage {
+    pub items: Vec<Instrument>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+impl InstrumentQuery {
+    /// Applies defaults and rejects out-of-range limits, unknown cursors and empty price ranges.
+    pub fn resolve(&self) -> Result<InstrumentListing, String> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_PAGE_LIMIT));
+        }
+
+        // Cursors are row offsets into the sorted, filtered list. They stay opaque to clients
+        // so a keyset cursor can replace them without changing the API.
+        let offset = match &self.cursor {
+            Some(cursor) => cursor.parse::<i64>().ok().filter(|offset| *offset >= 0)
+                .ok_or_else(|| format!("invalid cursor {:?}", cursor))?,
+            None => 0,
+        };
+
+        if let (Some(min), Some(max)) = (&self.min_price, &self.max_price) {
+            if min > max {
+                return Err(format!("min_price ({}) must not exceed max_price ({})", min, max));
+            }
+        }
+
+        Ok(InstrumentListing {
+            sort: self.sort.unwrap_or_default(),
+            order: self.order.unwrap_or_default(),
+            limit,
+            offset,
+            min_price: self.min_price.clone(),
+            max_price: self.max_price.clone(),
+            min_volume: self.min_volume,
+            change: self.change,
+            spark: self.spark.unwrap_or(true),
+        })
+    }
+}
+
+impl InstrumentListing {
+    pub fn matches(&self, instrument: &Instrument) -> bool {
+        let price = instrument.last_price.as_ref();
+        self.min_price.as_ref().is_none_or(|min| price.is_some_and(|p| p >= min))
+            && self.max_price.as_ref().is_none_or(|max| price.is_some_and(|p| p <= max))
+            && self.min_volume.is_none_or(|min| instrument.volume.is_some_and(|v| v >= min))
+            && self.change.is_none_or(|sign| instrument.change.as_ref().is_some_and(|c| sign.matches(c)))
+    }
+
+    /// Filters, sorts and pages an in-memory instrument list, ties broken by ID like the SQL query.
+    pub fn page(&self, instruments: Vec<Instrument>) -> InstrumentPage {
+        let mut matching: Vec<Instrument> = instruments.into_iter().filter(|i| self.matches(i)).collect();
+        matching.sort_by(|a, b| {
+            self.sort.compare(a, b, self.order).then(a.instrument_id.cmp(&b.instrument_id))
+        });
+
+        let total = matching.len() as i64;
+        let items = matching.into_iter().skip(self.offset as usize).take(self.limit as usize).collect();
+        InstrumentPage { items, total, next_cursor: self.next_cursor(total) }
+    }
+
+    pub fn next_cursor(&self, total: i64) -> Option<String> {
+        let next = self.offset + self.limit;
+        (next < total).then(|| next.to_string())
+    }
+}
+
+pub const DEFAULT_MOVERS_LIMIT: i64 = 15;
+pub const MAX_MOVERS_LIMIT: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoverDirection {
+    Gainers,
+    Losers,
+}
+
+/// What movers are ranked by. Gainers and losers are both ranked largest magnitude first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoverMetric {
+    /// `change`, in price units.
+    #[default]
+    Change,
+    /// `change` as a percentage of `prev_price`. Instruments without a positive `prev_price` are skipped.
+    PercentChange,
+    /// `volume`, i.e. the busiest names moving in the requested direction.
+    Volume,
+    /// `change * volume`, favouring moves that traded size.
+    VolumeWeightedChange,
+}
+
+impl MoverDirection {
+    pub fn sign(&self) -> ChangeSign {
+        match self {
+            MoverDirection::Gainers => ChangeSign::Up,
+            MoverDirection::Losers => ChangeSign::Down,
+        }
+    }
+}
+
+impl MoverMetric {
+    /// SQL expression over `market_data` computing this metric, NULL where it is undefined.
+    pub fn sql_expression(&self) -> &'static str {
+        match self {
+            MoverMetric::Change => "change",
+            MoverMetric::PercentChange => "CASE WHEN prev_price > 0 AND prev_price <> 'NaN' THEN change * 100 / prev_price END",
+            MoverMetric::Volume => "volume::numeric",
+            MoverMetric::VolumeWeightedChange => "change * volume",
+        }
+    }
+
+    /// This metric for `instrument`, matching `sql_expression`.
+    pub fn score(&self, instrument: &Instrument) -> Option<Decimal> {
+        let change = instrument.change.as_ref();
+        let volume = instrument.volume.map(Decimal::from);
+        match self {
+            MoverMetric::Change => change.cloned(),
+            MoverMetric::PercentChange => {
+                let prev_price = instrument.prev_price.as_ref().filter(|p| p.is_positive())?;
+                Some(&(change? * &Decimal::from(100)) / prev_price)
+            },
+            MoverMetric::Volume => volume,
+            MoverMetric::VolumeWeightedChange => Some(change? * &volume?),
+        }
+    }
+}
+
+/// Query parameters accepted by the movers endpoint. Every field is optional except `direction`,
+/// which the top gainers/losers aliases fill in.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MoversQuery {
+    pub direction: Option<MoverDirection>,
+    pub metric: Option<MoverMetric>,
+    pub limit: Option<i64>,
+    /// Leaves out instruments that traded less, or whose volume is unknown.
+    pub min_volume: Option<i64>,
+    /// Set to false to skip the 24h spark on each instrument.
+    pub spark: Option<bool>,
+}
+
+/// A movers request with defaults applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoversRequest {
+    pub direction: MoverDirection,
+    pub metric: MoverMetric,
+    pub limit: i64,
+    pub min_volume: Option<i64>,
+    pub spark: bool,
+}
+
+impl MoversQuery {
+    pub fn resolve(&self) -> Result<MoversRequest, String> {
+        let direction = self.direction.ok_or("direction must be \"gainers\" or \"losers\"")?;
+        let limit = self.limit.unwrap_or(DEFAULT_MOVERS_LIMIT);
+        if !(1..=MAX_MOVERS_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_MOVERS_LIMIT));
+        }
+
+        Ok(MoversRequest {
+            direction,
+            metric: self.metric.unwrap_or_default(),
+            limit,
+            min_volume: self.min_volume,
+            spark: self.spark.unwrap_or(true),
+        })
+    }
+}
+
+impl MoversRequest {
+    pub fn matches(&self, instrument: &Instrument) -> bool {
+        instrument.change.as_ref().is_some_and(|change| self.direction.sign().matches(change))
+            && self.min_volume.is_none_or(|min| instrument.volume.is_some_and(|v| v >= min))
+    }
+
+    /// Ranks an in-memory instrument list, ties broken by ID like the SQL query.
+    pub fn rank(&self, instruments: Vec<Instrument>) -> Vec<Instrument> {
+        let mut scored: Vec<(Decimal, Instrument)> = instruments
+            .into_iter()
+            .filter(|i| self.matches(i))
+            .filter_map(|i| Some((self.metric.score(&i)?.abs(), i)))
+            .collect();
+        scored.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then(a.instrument_id.cmp(&b.instrument_id))
+        });
+        scored.into_iter().take(self.limit as usize).map(|(_, i)| i).collect()
+    }
+}
+
+pub const DEFAULT_SEARCH_LIMIT: i64 = 15;
+pub const MAX_SEARCH_LIMIT: i64 = 100;
+
+/// Query parameters accepted by the search endpoint.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<i64>,
+    pub spark: Option<bool>,
+}
+
+/// A search with defaults applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchRequest {
+    pub term: String,
+    pub limit: i64,
+    pub spark: bool,
+}
+
+impl SearchQuery {
+    pub fn resolve(&self) -> Result<SearchRequest, String> {
+        let limit = self.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        if !(1..=MAX_SEARCH_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_SEARCH_LIMIT));
+        }
+
+        Ok(SearchRequest {
+            term: self.q.trim().to_string(),
+            limit,
+            spark: self.spark.unwrap_or(true),
+        })
+    }
+}
+
+/// Query parameters accepted by the chart endpoint. Every field is optional.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ChartQuery {
+    pub interval: Option<ChartInterval>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+}
+
+/// How much further back than the bars it needs `ChartRange::extended_back` looks.
+const LOOKBACK_SLACK: u32 = 3;
+
+/// A chart request with every bound filled in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChartRange {
+    pub interval: ChartInterval,
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub limit: i64,
+}
+
+impl ChartRange {
+    /// The same range with up to `bars` more bars before `from`, for indicators that need history.
+    /// The window reaches `LOOKBACK_SLACK` times further back than that, since markets are closed for
+    /// much of the calendar; the raised limit still keeps the extra rows to `bars`.
+    pub fn extended_back(&self, bars: usize) -> ChartRange {
+        let bars = u32::try_from(bars).unwrap_or(u32::MAX);
+        let back = bars.saturating_mul(LOOKBACK_SLACK);
+        let from = match self.interval {
+            ChartInterval::Month1 => self.from.checked_sub_months(Months::new(back)),
+            interval => {
+                let width = interval.bucket_end(self.from) - self.from;
+                width.checked_mul(i32::try_from(back).unwrap_or(i32::MAX)).and_then(|back| self.from.checked_sub_signed(back))
+            },
+        };
+        ChartRange {
+            from: from.unwrap_or(NaiveDateTime::MIN),
+            limit: self.limit.saturating_add(i64::from(bars)),
+            ..*self
+        }
+    }
+}
+
+impl ChartQuery {
+    /// Fills omitted bounds relative to `now` and rejects inverted ranges or out-of-range limits.
+    pub fn resolve(&self, now: NaiveDateTime) -> Result<ChartRange, String> {
+        let interval = self.interval.unwrap_or_default();
+        let to = self.to.unwrap_or(now);
+        let from = self.from.unwrap_or(to - interval.default_lookback());
+        let limit = self.limit.unwrap_or(DEFAULT_CHART_LIMIT);
+
+        if from > to {
+            return Err(format!("from ({}) must not be after to ({})", from, to));
+        }
+        if !(1..=MAX_CHART_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_CHART_LIMIT));
+        }
+
+        Ok(ChartRange { interval, from, to, limit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `None` prices stand in for NULL and `NaN` columns, which both read as missing.
+    fn instrument(instrument_id: i64, last_price: Option<&str>, change: Option<&str>, volume: Option<i64>) -> Instrument {
+        let decimal = |value: Option<&str>| value.map(|v| v.parse::<Decimal>().unwrap());
+        Instrument {
+            instrument_id,
+            code: format!("I{}", instrument_id),
+            symbol: format!("Instrument {}", instrument_id),
+            last_price: decimal(last_price),
+            prev_price: decimal(last_price),
+            change: decimal(change),
+            volume,
+            spark: vec![],
+        }
+    }
+
+    fn ids(instruments: &[Instrument]) -> Vec<i64> {
+        instruments.iter().map(|i| i.instrument_id).collect()
+    }
+
+    fn listing(query: InstrumentQuery) -> InstrumentListing {
+        query.resolve().unwrap()
+    }
+
+    #[test]
+    fn cursor_walks_every_page_once() {
+        let instruments: Vec<Instrument> = (1..=5).map(|id| instrument(id, Some("10"), Some("1"), Some(100))).collect();
+        let mut query = InstrumentQuery { limit: Some(2), ..Default::default() };
+        let mut seen = vec![];
+        loop {
+            let page = listing(query.clone()).page(instruments.clone());
+            assert_eq!(page.total, 5);
+            seen.extend(ids(&page.items));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn last_page_has_no_cursor() {
+        let instruments: Vec<Instrument> = (1..=4).map(|id| instrument(id, Some("10"), Some("1"), Some(100))).collect();
+        let page = listing(InstrumentQuery { limit: Some(2), cursor: Some("2".to_string()), ..Default::default() }).page(instruments.clone());
+        assert_eq!(ids(&page.items), vec![3, 4]);
+        assert_eq!(page.next_cursor, None);
+
+        let past_the_end = listing(InstrumentQuery { cursor: Some("10".to_string()), ..Default::default() }).page(instruments);
+        assert!(past_the_end.items.is_empty());
+        assert_eq!(past_the_end.next_cursor, None);
+    }
+
+    #[test]
+    fn bad_cursors_are_rejected() {
+        for cursor in ["-1", "abc", ""] {
+            assert!(InstrumentQuery { cursor: Some(cursor.to_string()), ..Default::default() }.resolve().is_err(), "{:?}", cursor);
+        }
+    }
+
+    #[test]
+    fn missing_values_sort_last_and_ties_go_by_id() {
+        let instruments = vec![
+            instrument(1, Some("10"), None, None),
+            instrument(2, Some("30"), Some("1"), None),
+            instrument(3, None, Some("1"), None),
+            instrument(4, Some("30"), Some("2"), None),
+        ];
+        let sorted = |sort, order| {
+            let query = InstrumentQuery { sort: Some(sort), order: Some(order), ..Default::default() };
+            ids(&listing(query).page(instruments.clone()).items)
+        };
+        assert_eq!(sorted(InstrumentSort::LastPrice, SortOrder::Asc), vec![1, 2, 4, 3]);
+        assert_eq!(sorted(InstrumentSort::LastPrice, SortOrder::Desc), vec![2, 4, 1, 3]);
+        assert_eq!(sorted(InstrumentSort::Change, SortOrder::Desc), vec![4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn filters_skip_missing_values() {
+        let instruments = vec![
+            instrument(1, Some("10"), Some("1"), Some(500)),
+            instrument(2, None, Some("-1"), None),
+            instrument(3, Some("20"), Some("0"), Some(50)),
+        ];
+        let min_price = listing(InstrumentQuery { min_price: Some(Decimal::from(5)), ..Default::default() }).page(instruments.clone());
+        assert_eq!((ids(&min_price.items), min_price.total), (vec![1, 3], 2));
+        let min_volume = listing(InstrumentQuery { min_volume: Some(100), ..Default::default() }).page(instruments.clone());
+        assert_eq!(ids(&min_volume.items), vec![1]);
+        let flat = listing(InstrumentQuery { change: Some(ChangeSign::Flat), ..Default::default() }).page(instruments);
+        assert_eq!(ids(&flat.items), vec![3]);
+    }
+
+    fn movers(direction: MoverDirection, metric: MoverMetric, instruments: &[Instrument]) -> Vec<i64> {
+        let query = MoversQuery { direction: Some(direction), metric: Some(metric), ..Default::default() };
+        ids(&query.resolve().unwrap().rank(instruments.to_vec()))
+    }
+
+    #[test]
+    fn movers_rank_by_magnitude_with_ties_by_id() {
+        let instruments = vec![
+            instrument(1, Some("10"), Some("-2"), Some(100)),
+            instrument(2, Some("10"), Some("3"), Some(100)),
+            instrument(3, Some("10"), Some("-5"), Some(100)),
+            instrument(4, Some("10"), Some("3"), Some(100)),
+            instrument(5, Some("10"), Some("0"), Some(100)),
+        ];
+        assert_eq!(movers(MoverDirection::Gainers, MoverMetric::Change, &instruments), vec![2, 4]);
+        assert_eq!(movers(MoverDirection::Losers, MoverMetric::Change, &instruments), vec![3, 1]);
+    }
+
+    #[test]
+    fn movers_skip_missing_and_nan_values() {
+        let instruments = vec![
+            // NULL or NaN change: neither a gainer nor a loser.
+            instrument(1, Some("10"), None, Some(100)),
+            instrument(2, Some("10"), Some("1"), Some(100)),
+            // NaN prev_price: no percent change.
+            Instrument { prev_price: None, ..instrument(3, Some("10"), Some("4"), Some(100)) },
+            Instrument { prev_price: Some(Decimal::zero()), ..instrument(4, Some("10"), Some("4"), Some(100)) },
+            // Unknown volume: no volume-based score.
+            instrument(5, Some("10"), Some("2"), None),
+        ];
+        assert_eq!(movers(MoverDirection::Gainers, MoverMetric::Change, &instruments), vec![3, 4, 5, 2]);
+        assert_eq!(movers(MoverDirection::Gainers, MoverMetric::PercentChange, &instruments), vec![5, 2]);
+        assert_eq!(movers(MoverDirection::Gainers, MoverMetric::VolumeWeightedChange, &instruments), vec![3, 4, 2]);
+        assert!(movers(MoverDirection::Losers, MoverMetric::Change, &instruments).is_empty());
+    }
+
+    #[test]
+    fn movers_apply_limit_and_min_volume() {
+        let instruments: Vec<Instrument> = (1..=5).map(|id| instrument(id, Some("10"), Some(&id.to_string()), Some(id * 100))).collect();
+        let query = MoversQuery { direction: Some(MoverDirection::Gainers), limit: Some(2), min_volume: Some(200), ..Default::default() };
+        assert_eq!(ids(&query.resolve().unwrap().rank(instruments.clone())), vec![5, 4]);
+        let busiest = MoversQuery { direction: Some(MoverDirection::Gainers), metric: Some(MoverMetric::Volume), min_volume: Some(300), ..Default::default() };
+        assert_eq!(ids(&busiest.resolve().unwrap().rank(instruments)), vec![5, 4, 3]);
+    }
+}
@@ -1 +1,3 @@
-pub mod instrument;
\ No newline at end of file
+pub mod decimal;
+pub mod instrument;
+pub mod protocol;
\ No newline at end of file
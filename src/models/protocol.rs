@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bars::Bar;
+use crate::models::instrument::{ChartInterval, Instrument, InstrumentKey, InstrumentUpdate, UpdatePayload};
+
+/// Subscribing to this symbol streams every instrument.
+pub const SUBSCRIBE_ALL: &str = "*";
+
+/// Control messages a subscriber may send to the price server, tagged by `op`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// `bars` adds live bars of those intervals for every subscribed instrument.
+    Subscribe {
+        instruments: Vec<InstrumentKey>,
+        #[serde(default)]
+        bars: Vec<ChartInterval>,
+    },
+    Unsubscribe {
+        instruments: Vec<InstrumentKey>,
+        #[serde(default)]
+        bars: Vec<ChartInterval>,
+    },
+    Heartbeat,
+}
+
+/// Messages a publisher may send on the publish endpoint, tagged by `op`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PublisherMessage {
+    /// Authenticates the connection, unless the handshake already did. Required before `update`.
+    Auth { api_key: String },
+    Update(UpdatePayload),
+    Heartbeat,
+}
+
+/// The latest update for one instrument, as carried in a resync.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SequencedUpdate {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub instrument: InstrumentUpdate,
+}
+
+/// The latest state of one bar, as carried in a resync.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SequencedBar {
+    pub seq: u64,
+    pub closed: bool,
+    #[serde(flatten)]
+    pub bar: Bar,
+}
+
+/// Messages the price server sends to clients, tagged by `op`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Confirms the instrument IDs now subscribed after resolving codes and symbols,
+    /// and every bar interval the connection now receives.
+    Subscribe { instruments: Vec<i64>, all: bool, bars: Vec<ChartInterval> },
+    Unsubscribe { instruments: Vec<i64>, all: bool, bars: Vec<ChartInterval> },
+    /// The connection is now authenticated as publisher `client_id`.
+    Auth { client_id: String },
+    /// Current state of newly subscribed instruments, or of every instrument for `*`, as of feed sequence `seq`.
+    /// Updates with a sequence number at or below `seq` are already reflected in it.
+    Snapshot { seq: u64, instruments: Vec<Instrument> },
+    Update {
+        seq: u64,
+        #[serde(flatten)]
+        instrument: InstrumentUpdate,
+    },
+    /// Updates between the previous event and `seq` were lost; a fresh snapshot of the subscribed instruments,
+    /// every instrument for `*`, follows.
+    Gap { seq: u64, down_for_ms: u64 },
+    /// An in-progress bar changed. Sent at most once a second per bar.
+    BarUpdate {
+        seq: u64,
+        #[serde(flatten)]
+        bar: Bar,
+    },
+    /// A bar ended and will not change again.
+    BarClose {
+        seq: u64,
+        #[serde(flatten)]
+        bar: Bar,
+    },
+    /// The client fell behind and updates from `from_seq` to `to_seq` were not sent one by one.
+    /// `updates` holds the latest of them per instrument, and `bars` the latest state of each bar;
+    /// `conflated` older ones were dropped in their favour.
+    /// When `lost` is non-zero some updates were never seen, so a snapshot of the subscriptions follows,
+    /// as it also does if the feed reported a gap meanwhile.
+    Resync { from_seq: u64, to_seq: u64, conflated: u64, lost: u64, updates: Vec<SequencedUpdate>, bars: Vec<SequencedBar> },
+    /// A publisher's update was stored. Sent once per update, in the order they arrived.
+    Ack { instrument_id: i32 },
+    /// A publisher's update was refused and nothing was stored.
+    Nack { instrument_id: i32, reason: String },
+    Error { message: String },
+    Heartbeat,
+}
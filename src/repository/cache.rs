@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use crate::models::instrument::{Instrument, InstrumentUpdate};
+use crate::repository::search::SearchIndex;
+
+/// Latest known state of every instrument, keyed by ID.
+/// Reads fall back to Postgres until the first full load marks the cache warm.
+#[derive(Default)]
+pub struct LastValueCache {
+    instruments: RwLock<BTreeMap<i64, Instrument>>,
+    index: RwLock<SearchIndex>,
+    warm: AtomicBool,
+}
+
+impl LastValueCache {
+    pub fn new() -> Self {
+        LastValueCache::default()
+    }
+
+    pub fn is_warm(&self) -> bool {
+        self.warm.load(Ordering::Acquire)
+    }
+
+    /// Replaces the whole cache with a fresh load from the database and marks it warm.
+    pub fn replace_all(&self, instruments: Vec<Instrument>) {
+        // Price updates never change codes or symbols, so the index only needs rebuilding here.
+        *self.index.write().unwrap() = SearchIndex::new(&instruments);
+        let instruments = instruments.into_iter().map(|i| (i.instrument_id, i)).collect();
+        *self.instruments.write().unwrap() = instruments;
+        self.warm.store(true, Ordering::Release);
+    }
+
+    /// Stops serving from memory until the next `replace_all`, e.g. after updates were lost.
+    pub fn invalidate(&self) {
+        self.warm.store(false, Ordering::Release);
+    }
+
+    /// Applies a price update. Instruments not yet cached are picked up by the next refresh.
+    pub fn apply(&self, update: &InstrumentUpdate) {
+        let mut instruments = self.instruments.write().unwrap();
+        if let Some(instrument) = instruments.get_mut(&i64::from(update.instrument_id)) {
+            instrument.last_price = Some(update.last_price.clone());
+            instrument.prev_price = Some(update.prev_price.clone());
+            instrument.change = Some(update.change.clone());
+        }
+    }
+
+    /// Every cached instrument, ordered by ID.
+    pub fn all(&self) -> Vec<Instrument> {
+        self.instruments.read().unwrap().values().cloned().collect()
+    }
+
+    /// The best `limit` matches for `term`, most relevant first.
+    pub fn search(&self, term: &str, limit: usize) -> Vec<Instrument> {
+        let ids = self.index.read().unwrap().search(term, limit);
+        let instruments = self.instruments.read().unwrap();
+        ids.iter().filter_map(|id| instruments.get(id).cloned()).collect()
+    }
+
+    /// Cached instruments matching `predicate`, ordered by ID.
+    pub fn filter(&self, predicate: impl Fn(&Instrument) -> bool) -> Vec<Instrument> {
+        self.instruments.read().unwrap().values().filter(|i| predicate(i)).cloned().collect()
+    }
+}
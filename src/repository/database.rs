@@ -1,152 +1,438 @@
-use std::{env, sync::{Arc, Mutex}};
-use bigdecimal::ToPrimitive;
-// use chrono::NaiveDateTime;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use bb8::{Pool, RunError};
+use bb8_postgres::PostgresConnectionManager;
 use pg_bigdecimal::PgNumeric;
-use tokio_postgres::{ Error, NoTls};
-use dotenv::dotenv;
-use tokio_postgres::Client;
+use serde::Serialize;
+use tokio_postgres::{ Client, Error, NoTls, Row};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::OnceCell;
+use tokio::task::{JoinError, JoinHandle};
+use async_trait::async_trait;
+
+use crate::models::decimal::Decimal;
+use crate::models::instrument::{ChartData, ChartRange, Instrument, InstrumentDetail, InstrumentKey, InstrumentListing, InstrumentUpdate, InstrumentPage, MoversRequest, SearchRequest, SparkPoint};
+use crate::feed::{Feed, FeedEvent, FeedPayload};
+use crate::repository::cache::LastValueCache;
+use crate::repository::repository::{with_spark, MarketDataRepository, RepositoryError};
+use crate::repository::search::SIMILARITY_THRESHOLD;
+use crate::shutdown::Shutdown;
+
+
+/// Reads a nullable `numeric` column. NULL and `NaN` both come back as `None`.
+fn numeric(row: &Row, column: &str) -> Option<Decimal> {
+    row.get::<_, Option<PgNumeric>>(column).and_then(|numeric| numeric.n).map(Decimal)
+}
 
-use crate::models::instrument::{ChartData, Instrument, InstrumentDetail, SparkPoint};
+pub type DbPool = Pool<PostgresConnectionManager<NoTls>>;
+pub type DbError = RunError<Error>;
+
+/// Sizing, timeout and health-check settings for the Postgres connection pool.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    pub test_on_check_out: bool,
+}
 
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 16,
+            min_idle: None,
+            connection_timeout: Duration::from_secs(5),
+            idle_timeout: Some(Duration::from_secs(600)),
+            max_lifetime: Some(Duration::from_secs(1800)),
+            test_on_check_out: true,
+        }
+    }
+}
 
-// fn pg_numeric_to_f64(numeric: PgNumeric) -> f64 {
-//     numeric.n.unwrap().to_f64().unwrap()
-// }
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PoolStats {
+    pub max_size: u32,
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub in_use: u32,
+}
+
+/// Escapes `%`, `_` and `\\` so `term` matches literally inside a LIKE pattern.
+fn escape_like(term: &str) -> String {
+    let mut escaped = String::with_capacity(term.len());
+    for c in term.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Splits keys into numeric IDs and upper-cased codes/symbols.
+fn split_keys(keys: &[InstrumentKey]) -> (Vec<i64>, Vec<String>) {
+    let mut ids = Vec::new();
+    let mut names = Vec::new();
+    for key in keys {
+        match key {
+            InstrumentKey::Id(id) => ids.push(*id),
+            InstrumentKey::Symbol(name) => names.push(name.to_uppercase()),
+        }
+    }
+    (ids, names)
+}
 
 pub struct Database {
-    pub instruments: Arc<Mutex<Vec<Instrument>>>,
+    cache: LastValueCache,
+    pool: DbPool,
+    max_size: u32,
+    /// Whether `market_data` has the optional `shares_outstanding` column, checked on first use.
+    has_shares_outstanding: OnceCell<bool>,
+    /// Whether the pg_trgm extension is installed, for fuzzy searches while the cache is cold.
+    has_pg_trgm: OnceCell<bool>,
 }
 
-impl Database {
-    pub fn new() -> Self {
-        let instruments = Arc::new(Mutex::new(vec![]));
-        Database { instruments }
+type LoadResult = Result<Result<Vec<Instrument>, RepositoryError>, JoinError>;
+
+enum SyncStep {
+    Refresh,
+    Loaded(LoadResult),
+    Event(Result<FeedEvent, RecvError>),
+}
+
+/// A cache reload running alongside `sync_cache`'s loop, with the updates that arrived meanwhile.
+#[derive(Default)]
+struct CacheReload {
+    loading: Option<JoinHandle<Result<Vec<Instrument>, RepositoryError>>>,
+    /// Applied again on top of the load, which may have read the database before them.
+    /// Re-applying an update the load already saw is harmless.
+    updates: Vec<InstrumentUpdate>,
+    /// Updates were lost while loading, so the result may not reflect them either.
+    missed: bool,
+}
+
+impl CacheReload {
+    /// Starts a load unless one is already running.
+    fn start(&mut self, database: &Arc<Database>) {
+        if self.loading.is_none() {
+            let database = database.clone();
+            self.loading = Some(tokio::spawn(async move { database.load_instruments().await }));
+        }
     }
 
-    async fn get_db_client() -> Result<Client, Error> {
-        dotenv().ok();
-        let connection_string = env::var("DATABASE_URL")
-            .expect("DATABASE_URL must be set");
-    
-        let (client, connection) = tokio_postgres::connect(&connection_string, NoTls)
+    fn record(&mut self, update: InstrumentUpdate) {
+        if self.loading.is_some() {
+            self.updates.push(update);
+        }
+    }
+
+    /// Stops serving the cache, which lacks some updates, until a load started after them completes.
+    fn missed(&mut self, database: &Arc<Database>) {
+        database.cache.invalidate();
+        if self.loading.is_some() {
+            self.missed = true;
+        } else {
+            self.start(database);
+        }
+    }
+
+    /// Resolves when the running load completes; never while none is.
+    async fn finished(&mut self) -> LoadResult {
+        match &mut self.loading {
+            Some(loading) => loading.await,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn finish(&mut self, database: &Arc<Database>, loaded: LoadResult) {
+        self.loading = None;
+        let updates = std::mem::take(&mut self.updates);
+        if std::mem::take(&mut self.missed) {
+            self.start(database);
+            return;
+        }
+        match loaded {
+            Ok(Ok(instruments)) => {
+                let count = instruments.len();
+                database.cache.replace_all(instruments);
+                for update in &updates {
+                    database.cache.apply(update);
+                }
+                println!("Instrument cache loaded {} instruments", count);
+            },
+            // An invalidated cache stays cold, so reads go to Postgres until a reload succeeds.
+            Ok(Err(e)) => eprintln!("error loading instrument cache: {:?}", e),
+            Err(e) => eprintln!("instrument cache load failed: {}", e),
+        }
+    }
+}
+
+impl Database {
+    pub async fn new(connection_string: &str, config: PoolConfig) -> Result<Self, Error> {
+        let manager = PostgresConnectionManager::new_from_stringlike(connection_string, NoTls)?;
+        let pool = Pool::builder()
+            .max_size(config.max_size)
+            .min_idle(config.min_idle)
+            .connection_timeout(config.connection_timeout)
+            .idle_timeout(config.idle_timeout)
+            .max_lifetime(config.max_lifetime)
+            .test_on_check_out(config.test_on_check_out)
+            .build(manager)
             .await?;
-    
+
+        Ok(Database { cache: LastValueCache::new(), pool, max_size: config.max_size, has_shares_outstanding: OnceCell::new(), has_pg_trgm: OnceCell::new() })
+    }
+
+    /// Every instrument with its spark, as the cache holds them.
+    async fn load_instruments(&self) -> Result<Vec<Instrument>, RepositoryError> {
+        let client = self.pool.get().await?;
+
+        let rows = client.query("SELECT instrument_id, code, symbol, last_price, prev_price, change, volume FROM market_data ORDER BY instrument_id ASC", &[]).await?;
+        Ok(Database::instruments_from_rows(&client, &rows, true).await?)
+    }
+
+    /// Checks the schema once for `market_data.shares_outstanding`, which older databases lack.
+    async fn has_shares_outstanding(&self, client: &Client) -> Result<bool, Error> {
+        self.has_shares_outstanding.get_or_try_init(|| async {
+            let row = client.query_one(
+                "SELECT EXISTS (
+                    SELECT 1 FROM information_schema.columns
+                    WHERE table_schema = current_schema() AND table_name = 'market_data' AND column_name = 'shares_outstanding'
+                ) AS present",
+                &[]
+            ).await?;
+            Ok(row.get("present"))
+        }).await.copied()
+    }
+
+    /// Checks once whether pg_trgm is installed, so searches can use its `similarity()`.
+    async fn has_pg_trgm(&self, client: &Client) -> Result<bool, Error> {
+        self.has_pg_trgm.get_or_try_init(|| async {
+            let row = client.query_one("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'pg_trgm') AS present", &[]).await?;
+            Ok(row.get("present"))
+        }).await.copied()
+    }
+
+    /// Keeps the last-value cache current: warms it, applies every price update from the feed,
+    /// and reloads it every `refresh` (for volume, spark and new instruments, which NOTIFY does not carry)
+    /// or whenever updates may have been missed. Stops when `shutdown` is triggered.
+    pub fn sync_cache(self: Arc<Self>, feed: Arc<Feed>, refresh: Duration, shutdown: Arc<Shutdown>) -> JoinHandle<()> {
+        // Subscribe before the first load so no update between the two is lost.
+        let mut rx = feed.subscribe();
         tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
+            let mut refresh = tokio::time::interval(refresh);
+            let mut reload = CacheReload::default();
+            loop {
+                // Loads run in their own task so this loop keeps draining the feed meanwhile.
+                let step = tokio::select! {
+                    _ = refresh.tick() => SyncStep::Refresh,
+                    loaded = reload.finished() => SyncStep::Loaded(loaded),
+                    event = rx.recv() => SyncStep::Event(event),
+                    _ = shutdown.wait() => break,
+                };
+                match step {
+                    SyncStep::Refresh => reload.start(&self),
+                    SyncStep::Loaded(loaded) => reload.finish(&self, loaded),
+                    SyncStep::Event(Ok(event)) => match event.payload {
+                        FeedPayload::Price(update) => {
+                            self.cache.apply(&update);
+                            reload.record(update);
+                        },
+                        FeedPayload::Gap { .. } => reload.missed(&self),
+                        FeedPayload::Bars(_) => {},
+                    },
+                    SyncStep::Event(Err(RecvError::Lagged(skipped))) => {
+                        eprintln!("Instrument cache missed {} updates, reloading", skipped);
+                        reload.missed(&self);
+                    },
+                    SyncStep::Event(Err(RecvError::Closed)) => break,
+                }
             }
-        });
-    
-        Ok(client)
+        })
     }
 
-    pub async fn load(&self) -> Result<Vec<Instrument>, Error> {
-        let client = Database::get_db_client().await?;
+    /// Up to 20 points of the last 24h of price history per instrument, oldest first,
+    /// fetched for all of `instrument_ids` in one query.
+    async fn sparks_for(client: &Client, instrument_ids: &[i64]) -> Result<HashMap<i64, Vec<SparkPoint>>, Error> {
+        let spark_rows = client
+            .query(
+                "SELECT instrument_id, price
+                FROM (
+                    SELECT
+                        instrument_id,
+                        price,
+                        recorded_at,
+                        ROW_NUMBER() OVER (PARTITION BY instrument_id ORDER BY recorded_at ASC) AS point
+                    FROM market_price_history
+                    WHERE instrument_id = ANY($1) AND recorded_at >= NOW() - INTERVAL '24 hours'
+                ) history
+                WHERE point <= 20
+                ORDER BY instrument_id, recorded_at ASC",
+                &[&instrument_ids]
+            )
+            .await?;
 
-        let rows = client.query("SELECT instrument_id, code, symbol, last_price, prev_price, change, volume FROM market_data ORDER BY instrument_id ASC LIMIT 5", &[]).await?;
-        let mut new_instruments = Vec::new();
-        for row in rows {
-            let last_price: PgNumeric = row.get(3);
-            let prev_price: PgNumeric = row.get(4);
-            let change: PgNumeric = row.get(5);
-            let instrument_id: i64 = row.get("instrument_id");
-            let spark_rows = client
-                .query(
-                    "SELECT price FROM market_price_history WHERE instrument_id = $1 AND recorded_at >= NOW() - INTERVAL '24 hours' ORDER BY recorded_at ASC LIMIT 20",
-                    &[&instrument_id]
-                )
-                .await?;
-            let spark: Vec<SparkPoint> = spark_rows
-                .iter()
-                .enumerate()
-                .map(|(i, spark_row)| {
-                    let price: PgNumeric = spark_row.get("price"); 
-                    let x_value = i as f64;
-                    let y_value = price.n.unwrap().clone().to_f64().unwrap();
-                    SparkPoint {
-                        x: x_value,
-                        y: y_value,
-                    }
-                })
-                .collect();
-            
-            let instrument = Instrument {
-                instrument_id: row.get("instrument_id"),
-                code: row.get("code"),
-                symbol: row.get("symbol"),
-                last_price: last_price.n.unwrap().clone().to_f64().unwrap(),
-                prev_price: prev_price.n.unwrap().clone().to_f64().unwrap(),
-                change: change.n.unwrap().clone().to_f64().unwrap(),
-                volume: row.get("volume"),
-                spark
+        let mut sparks: HashMap<i64, Vec<SparkPoint>> = HashMap::new();
+        for spark_row in spark_rows {
+            // Sparks are only for plotting, so points without a usable price are skipped.
+            let Some(price) = numeric(&spark_row, "price") else {
+                continue;
             };
+            let spark = sparks.entry(spark_row.get("instrument_id")).or_default();
+            spark.push(SparkPoint {
+                x: spark.len() as f64,
+                y: price.to_f64(),
+            });
+        }
+
+        Ok(sparks)
+    }
+
+    /// Maps a `market_data` row (instrument_id, code, symbol, last_price, prev_price, change, volume)
+    /// to an instrument with an empty spark.
+    fn instrument_from_row(row: &Row) -> Instrument {
+        Instrument {
+            instrument_id: row.get("instrument_id"),
+            code: row.get("code"),
+            symbol: row.get("symbol"),
+            last_price: numeric(row, "last_price"),
+            prev_price: numeric(row, "prev_price"),
+            change: numeric(row, "change"),
+            volume: row.get("volume"),
+            spark: vec![],
+        }
+    }
 
-            new_instruments.push(instrument);
+    /// Maps `market_data` rows in order, attaching sparks with a single extra query when `spark` is set.
+    async fn instruments_from_rows(client: &Client, rows: &[Row], spark: bool) -> Result<Vec<Instrument>, Error> {
+        let mut instruments: Vec<Instrument> = rows.iter().map(Database::instrument_from_row).collect();
+        if spark && !instruments.is_empty() {
+            let ids: Vec<i64> = instruments.iter().map(|i| i.instrument_id).collect();
+            let mut sparks = Database::sparks_for(client, &ids).await?;
+            for instrument in &mut instruments {
+                instrument.spark = sparks.remove(&instrument.instrument_id).unwrap_or_default();
+            }
         }
+        Ok(instruments)
+    }
 
-        Ok(new_instruments)
+    pub fn pool_stats(&self) -> PoolStats {
+        let state = self.pool.state();
+        PoolStats {
+            max_size: self.max_size,
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            in_use: state.connections - state.idle_connections,
+        }
     }
+}
+
+#[async_trait]
+impl MarketDataRepository for Database {
+    async fn load(&self, listing: &InstrumentListing) -> Result<InstrumentPage, RepositoryError> {
+        if self.cache.is_warm() {
+            let mut page = listing.page(self.cache.all());
+            page.items = with_spark(page.items, listing.spark);
+            return Ok(page);
+        }
 
-    pub async fn get_chart_data_by_id(&self, instrument_id: i64) -> Result<Vec<ChartData>, Error> {
-        let client = Database::get_db_client().await?;
+        let client = self.pool.get().await?;
 
-        // let query: String = "".to_owned();
+        // The change condition, sort column and direction come from fixed sets, never from user input.
+        let filter = format!(
+            "WHERE ($1::numeric IS NULL OR (last_price >= $1 AND last_price <> 'NaN'))
+                AND ($2::numeric IS NULL OR last_price <= $2)
+                AND ($3::bigint IS NULL OR volume >= $3)
+                AND {change}",
+            change = listing.change.map_or("TRUE", |sign| sign.sql_condition())
+        );
+        let min_price = listing.min_price.as_ref().map(|price| PgNumeric::new(Some(price.0.clone())));
+        let max_price = listing.max_price.as_ref().map(|price| PgNumeric::new(Some(price.0.clone())));
+
+        let total: i64 = client.query_one(
+            format!("SELECT COUNT(*) FROM market_data {}", filter).as_str(),
+            &[&min_price, &max_price, &listing.min_volume]
+        ).await?.get(0);
 
         let rows = client.query(
-            "WITH grouped_data AS (
+            format!(
+                "SELECT instrument_id, code, symbol, last_price, prev_price, change, volume
+                FROM market_data
+                {filter}
+                ORDER BY {column} {order} NULLS LAST, instrument_id ASC
+                LIMIT $4 OFFSET $5",
+                column = listing.sort.sql_column(),
+                order = listing.order.sql()
+            ).as_str(),
+            &[&min_price, &max_price, &listing.min_volume, &listing.limit, &listing.offset]
+        ).await?;
+
+        Ok(InstrumentPage {
+            items: Database::instruments_from_rows(&client, &rows, listing.spark).await?,
+            total,
+            next_cursor: listing.next_cursor(total),
+        })
+    }
+
+    async fn get_chart_data_by_id(&self, instrument_id: i64, range: &ChartRange) -> Result<Vec<ChartData>, RepositoryError> {
+        let client = self.pool.get().await?;
+
+        // The bucket expression comes from a fixed set on ChartInterval, never from user input.
+        let query = format!(
+            "WITH bars AS (
                 SELECT
-                    date_trunc('hour', timestamp) AS hour_timestamp,
+                    {bucket} AS bucket_timestamp,
                     instrument_id,
-                    MIN(timestamp) AS first_timestamp,
-                    MAX(timestamp) AS last_timestamp,
-                    MIN(low_price) AS low_price,
+                    (array_agg(open_price ORDER BY timestamp ASC))[1] AS open_price,
+                    (array_agg(close_price ORDER BY timestamp DESC))[1] AS close_price,
+                    (array_agg(chart_data_id ORDER BY timestamp DESC))[1] AS chart_data_id,
                     MAX(high_price) AS high_price,
+                    MIN(low_price) AS low_price,
                     SUM(volume) AS volume
                 FROM
                     market_data_chart
                 WHERE
-                    timestamp >= '2024-10-03 08:02:00'
-                    AND timestamp <= '2024-10-03 17:02:59'
-                    AND instrument_id = $1
+                    instrument_id = $1
+                    AND timestamp >= $2
+                    AND timestamp <= $3
                 GROUP BY
-                    date_trunc('hour', timestamp), instrument_id
+                    bucket_timestamp, instrument_id
+                ORDER BY
+                    bucket_timestamp DESC
+                LIMIT $4
             )
             SELECT
-                (SELECT open_price FROM market_data_chart WHERE instrument_id = gd.instrument_id AND timestamp = gd.first_timestamp) AS open_price,
-                (SELECT close_price FROM market_data_chart WHERE instrument_id = gd.instrument_id AND timestamp = gd.last_timestamp) AS close_price,
-                (SELECT chart_data_id FROM market_data_chart WHERE instrument_id = gd.instrument_id AND timestamp = gd.last_timestamp) AS chart_data_id,
-                gd.instrument_id,
-                gd.high_price,
-                gd.low_price,
-                gd.volume,
-                gd.hour_timestamp
+                chart_data_id,
+                instrument_id,
+                open_price,
+                close_price,
+                high_price,
+                low_price,
+                volume,
+                bucket_timestamp
             FROM
-                grouped_data gd
+                bars
             ORDER BY
-                gd.hour_timestamp ASC;
-            
-            
-            ", 
-             &[&instrument_id]
+                bucket_timestamp ASC",
+            bucket = range.interval.sql_bucket()
+        );
+
+        let rows = client.query(
+            query.as_str(),
+            &[&instrument_id, &range.from, &range.to, &range.limit]
         ).await?;
 
         let mut chart_datas = Vec::new();
         for row in rows {
-            let instrument_id: i64 = row.get("instrument_id");
-            let open_price: PgNumeric = row.get("open_price");
-            let close_price: PgNumeric = row.get("close_price");
-            let high_price: PgNumeric = row.get("high_price");
-            let low_price: PgNumeric = row.get("low_price");
-            let volume: PgNumeric = row.get("volume");
-
             let chart_data = ChartData {
-                instrument_id: instrument_id,
-                open_price: open_price.n.unwrap().clone().to_f64().unwrap(),
-                close_price: close_price.n.unwrap().clone().to_f64().unwrap(),
-                high_price: high_price.n.unwrap().clone().to_f64().unwrap(),
-                low_price: low_price.n.unwrap().clone().to_f64().unwrap(),
-                volume: volume.n.unwrap().clone().to_f64().unwrap(),
-                timestamp: row.get("hour_timestamp"),
+                instrument_id: row.get("instrument_id"),
+                open_price: numeric(&row, "open_price"),
+                close_price: numeric(&row, "close_price"),
+                high_price: numeric(&row, "high_price"),
+                low_price: numeric(&row, "low_price"),
+                volume: numeric(&row, "volume"),
+                timestamp: row.get("bucket_timestamp"),
                 chart_data_id: row.get("chart_data_id"),
             };
             chart_datas.push(chart_data);       
@@ -155,202 +441,247 @@ impl Database {
         Ok(chart_datas)
     }
     
-    pub async fn top_losers(&self) -> Result<Vec<Instrument>, Error> {
-        let client = Database::get_db_client().await?;
+    async fn movers(&self, request: &MoversRequest) -> Result<Vec<Instrument>, RepositoryError> {
+        if self.cache.is_warm() {
+            return Ok(with_spark(request.rank(self.cache.all()), request.spark));
+        }
 
+        let client = self.pool.get().await?;
+
+        // The direction condition and metric expression come from fixed sets, never from user input.
         let rows = client.query(
-            "SELECT instrument_id, code, symbol, last_price, prev_price, change, volume
-            FROM market_data 
-            WHERE change < 0.0
-            ORDER BY change ASC 
-            LIMIT 15", 
-             &[]
+            format!(
+                "SELECT instrument_id, code, symbol, last_price, prev_price, change, volume
+                FROM market_data
+                WHERE {direction}
+                    AND ($1::bigint IS NULL OR volume >= $1)
+                    AND ({metric}) IS NOT NULL
+                ORDER BY ABS({metric}) DESC, instrument_id ASC
+                LIMIT $2",
+                direction = request.direction.sign().sql_condition(),
+                metric = request.metric.sql_expression()
+            ).as_str(),
+            &[&request.min_volume, &request.limit]
         ).await?;
+        Ok(Database::instruments_from_rows(&client, &rows, request.spark).await?)
+    }
 
-        
-        let mut new_instruments = Vec::new();
-        for row in rows {
-            let last_price: PgNumeric = row.get(3);
-            let prev_price: PgNumeric = row.get(4);
-            let change: PgNumeric = row.get(5);
-            let instrument_id: i64 = row.get("instrument_id");
-            let spark_rows = client
-                .query(
-                    "SELECT price FROM market_price_history WHERE instrument_id = $1 AND recorded_at >= NOW() - INTERVAL '24 hours' ORDER BY recorded_at ASC LIMIT 20",
-                    &[&instrument_id]
-                )
-                .await?;
-            let spark: Vec<SparkPoint> = spark_rows
-                .iter()
-                .enumerate()
-                .map(|(i, spark_row)| {
-                    let price: PgNumeric = spark_row.get("price"); 
-                    let x_value = i as f64;
-                    let y_value = price.n.unwrap().clone().to_f64().unwrap();
-                    SparkPoint {
-                        x: x_value,
-                        y: y_value,
-                    }
-                })
-                .collect();
-            let instrument = Instrument {
+    async fn search_instruments(&self, request: &SearchRequest) -> Result<Vec<Instrument>, RepositoryError> {
+        if self.cache.is_warm() {
+            return Ok(with_spark(self.cache.search(&request.term, request.limit as usize), request.spark));
+        }
+
+        let client = self.pool.get().await?;
+
+        // Until the cache is warm the same tiers are ranked over ILIKE. Near misses need pg_trgm,
+        // and typos too short for trigrams to catch are only found by the cache.
+        let term = escape_like(&request.term);
+        let fuzzy = self.has_pg_trgm(&client).await?;
+        let (near_miss, closeness) = if fuzzy {
+            (
+                format!("OR similarity(code, $3) >= {threshold} OR similarity(symbol, $3) >= {threshold}", threshold = SIMILARITY_THRESHOLD),
+                "GREATEST(similarity(code, $3), similarity(symbol, $3)) DESC,",
+            )
+        } else {
+            (String::new(), "")
+        };
+        let query = format!(
+            "SELECT instrument_id, code, symbol, last_price, prev_price, change, volume
+            FROM market_data
+            WHERE code ILIKE '%' || $1 || '%' OR symbol ILIKE '%' || $1 || '%' {near_miss}
+            ORDER BY
+                CASE
+                    WHEN code ILIKE $1 THEN 0
+                    WHEN symbol ILIKE $1 THEN 1
+                    WHEN code ILIKE $1 || '%' THEN 2
+                    WHEN symbol ILIKE $1 || '%' OR symbol ILIKE '% ' || $1 || '%' THEN 3
+                    WHEN code ILIKE '%' || $1 || '%' OR symbol ILIKE '%' || $1 || '%' THEN 4
+                    ELSE 5
+                END,
+                {closeness}
+                LOWER(code),
+                instrument_id
+            LIMIT $2"
+        );
+        let rows = if fuzzy {
+            client.query(query.as_str(), &[&term, &request.limit, &request.term]).await?
+        } else {
+            client.query(query.as_str(), &[&term, &request.limit]).await?
+        };
+        Ok(Database::instruments_from_rows(&client, &rows, request.spark).await?)
+    }
+
+    async fn get_instrument_detail(&self, key: &InstrumentKey) -> Result<InstrumentDetail, RepositoryError> {
+        let (ids, names) = split_keys(std::slice::from_ref(key));
+        let client = self.pool.get().await?;
+        let market_cap = if self.has_shares_outstanding(&client).await? {
+            "last_price * shares_outstanding"
+        } else {
+            "NULL::numeric"
+        };
+
+        // Changes compare the live price with the last chart close at or before each horizon,
+        // so they stay NULL until the history reaches back that far.
+        let query = format!("
+            WITH instrument AS (
+                SELECT instrument_id, code, symbol, last_price, prev_price, change, {} AS market_cap
+                FROM market_data
+                WHERE instrument_id = ANY($1) OR UPPER(code) = ANY($2) OR UPPER(symbol) = ANY($2)
+                ORDER BY instrument_id ASC
+                LIMIT 1
+            ),
+            history AS (
+                SELECT timestamp, close_price, high_price, low_price, volume
+                FROM market_data_chart
+                WHERE instrument_id = (SELECT instrument_id FROM instrument)
+            ),
+            reference AS (
+                SELECT
+                    (SELECT close_price FROM history WHERE timestamp < date_trunc('day', NOW()) ORDER BY timestamp DESC LIMIT 1) AS today,
+                    (SELECT close_price FROM history WHERE timestamp <= NOW() - INTERVAL '7 days' ORDER BY timestamp DESC LIMIT 1) AS day_7,
+                    (SELECT close_price FROM history WHERE timestamp <= NOW() - INTERVAL '30 days' ORDER BY timestamp DESC LIMIT 1) AS day_30,
+                    (SELECT close_price FROM history WHERE timestamp <= NOW() - INTERVAL '90 days' ORDER BY timestamp DESC LIMIT 1) AS day_90,
+                    (SELECT close_price FROM history WHERE timestamp <= NOW() - INTERVAL '180 days' ORDER BY timestamp DESC LIMIT 1) AS day_180,
+                    (SELECT close_price FROM history WHERE timestamp <= NOW() - INTERVAL '1 year' ORDER BY timestamp DESC LIMIT 1) AS year_1
+            )
+            SELECT
+                instrument.instrument_id,
+                code,
+                symbol,
+                last_price,
+                prev_price,
+                change,
+                ROUND((last_price - reference.today) / NULLIF(reference.today, 0) * 100, 2) AS today_change,
+                ROUND((last_price - reference.day_7) / NULLIF(reference.day_7, 0) * 100, 2) AS day_7_change,
+                ROUND((last_price - reference.day_30) / NULLIF(reference.day_30, 0) * 100, 2) AS day_30_change,
+                ROUND((last_price - reference.day_90) / NULLIF(reference.day_90, 0) * 100, 2) AS day_90_change,
+                ROUND((last_price - reference.day_180) / NULLIF(reference.day_180, 0) * 100, 2) AS day_180_change,
+                ROUND((last_price - reference.year_1) / NULLIF(reference.year_1, 0) * 100, 2) AS year_1_change,
+                (SELECT MAX(high_price) FROM history WHERE timestamp >= NOW() - INTERVAL '52 weeks') AS high_52w,
+                (SELECT MIN(low_price) FROM history WHERE timestamp >= NOW() - INTERVAL '52 weeks') AS low_52w,
+                (SELECT ROUND(SUM(volume) / NULLIF(COUNT(DISTINCT timestamp::date), 0), 2)
+                    FROM history WHERE timestamp >= NOW() - INTERVAL '30 days') AS avg_volume,
+                (SELECT ROUND(SUM((high_price + low_price + close_price) / 3 * volume) / NULLIF(SUM(volume), 0), 4)
+                    FROM history WHERE timestamp >= NOW() - INTERVAL '24 hours') AS vwap,
+                market_cap,
+                (SELECT COALESCE(SUM(volume), 0) FROM history WHERE timestamp >= NOW() - INTERVAL '24 hours') AS vol_24,
+                (SELECT MAX(high_price) FROM history WHERE timestamp >= NOW() - INTERVAL '24 hours') AS high_24,
+                (SELECT MIN(low_price) FROM history WHERE timestamp >= NOW() - INTERVAL '24 hours') AS low_24,
+                (SELECT SUM(volume) FROM history) AS total_vol
+            FROM instrument, reference;
+            ", market_cap);
+        let row = client.query_opt(&query, &[&ids, &names]).await?
+            .ok_or(RepositoryError::NotFound)?;
+
+            let instrument_detail = InstrumentDetail {
                 instrument_id: row.get("instrument_id"),
-                code: row.get("code"),
-                symbol: row.get("symbol"),
-                last_price: last_price.n.unwrap().clone().to_f64().unwrap(),
-                prev_price: prev_price.n.unwrap().clone().to_f64().unwrap(),
-                change: change.n.unwrap().clone().to_f64().unwrap(),
-                volume: row.get("volume"),
-                spark
+                code:           row.get("code"),
+                symbol:         row.get("symbol"),
+                last_price:     numeric(&row, "last_price"),
+                prev_price:     numeric(&row, "prev_price"),
+                change:         numeric(&row, "change"),
+                today_change:   numeric(&row, "today_change"),
+                day_7_change:   numeric(&row, "day_7_change"),
+                day_30_change:  numeric(&row, "day_30_change"),
+                day_90_change:  numeric(&row, "day_90_change"),
+                day_180_change: numeric(&row, "day_180_change"),
+                year_1_change:  numeric(&row, "year_1_change"),
+                high_52w:       numeric(&row, "high_52w"),
+                low_52w:        numeric(&row, "low_52w"),
+                avg_volume:     numeric(&row, "avg_volume"),
+                vwap:           numeric(&row, "vwap"),
+                market_cap:     numeric(&row, "market_cap"),
+                vol_24:         numeric(&row, "vol_24"),
+                high_24:        numeric(&row, "high_24"),
+                low_24:         numeric(&row, "low_24"),
+                total_vol:      numeric(&row, "total_vol"),
             };
+        Ok(instrument_detail)
+    }
 
-            new_instruments.push(instrument);
+    async fn get_instruments_by_ids(&self, ids: &[i64]) -> Result<Vec<Instrument>, RepositoryError> {
+        if self.cache.is_warm() {
+            return Ok(self.cache.filter(|i| ids.contains(&i.instrument_id)));
         }
 
-        Ok(new_instruments)
+        let client = self.pool.get().await?;
+
+        let rows = client.query(
+            "SELECT instrument_id, code, symbol, last_price, prev_price, change, volume
+             FROM market_data
+             WHERE instrument_id = ANY($1)
+             ORDER BY instrument_id ASC",
+             &[&ids]
+        ).await?;
+        Ok(Database::instruments_from_rows(&client, &rows, true).await?)
     }
 
+    async fn get_all_instruments(&self) -> Result<Vec<Instrument>, RepositoryError> {
+        if self.cache.is_warm() {
+            return Ok(self.cache.all());
+        }
 
-    pub async fn top_gainers(&self) -> Result<Vec<Instrument>, Error> {
-        let client = Database::get_db_client().await?;
+        let client = self.pool.get().await?;
 
         let rows = client.query(
             "SELECT instrument_id, code, symbol, last_price, prev_price, change, volume
-            FROM market_data 
-            WHERE change > 0.0 
-            ORDER BY change DESC 
-            LIMIT 15", 
+             FROM market_data
+             ORDER BY instrument_id ASC",
              &[]
         ).await?;
+        Ok(Database::instruments_from_rows(&client, &rows, true).await?)
+    }
 
-        
-        let mut new_instruments = Vec::new();
-        for row in rows {
-            let last_price: PgNumeric = row.get(3);
-            let prev_price: PgNumeric = row.get(4);
-            let change: PgNumeric = row.get(5);
-            let instrument_id: i64 = row.get("instrument_id");
-            let spark_rows = client
-                .query(
-                    "SELECT price FROM market_price_history WHERE instrument_id = $1 AND recorded_at >= NOW() - INTERVAL '24 hours' ORDER BY recorded_at ASC LIMIT 20",
-                    &[&instrument_id]
-                )
-                .await?;
-            let spark: Vec<SparkPoint> = spark_rows
-                .iter()
-                .enumerate()
-                .map(|(i, spark_row)| {
-                    let price: PgNumeric = spark_row.get("price"); 
-                    let x_value = i as f64;
-                    let y_value = price.n.unwrap().clone().to_f64().unwrap();
-                    SparkPoint {
-                        x: x_value,
-                        y: y_value,
-                    }
-                })
-                .collect();
-            let instrument = Instrument {
-                instrument_id: row.get("instrument_id"),
-                code: row.get("code"),
-                symbol: row.get("symbol"),
-                last_price: last_price.n.unwrap().clone().to_f64().unwrap(),
-                prev_price: prev_price.n.unwrap().clone().to_f64().unwrap(),
-                change: change.n.unwrap().clone().to_f64().unwrap(),
-                volume: row.get("volume"),
-                spark
-            };
-
-            new_instruments.push(instrument);
+    async fn resolve_instruments(&self, keys: &[InstrumentKey]) -> Result<Vec<i64>, RepositoryError> {
+        let (ids, names) = split_keys(keys);
+        if ids.is_empty() && names.is_empty() {
+            return Ok(vec![]);
         }
 
-        Ok(new_instruments)
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT instrument_id
+             FROM market_data
+             WHERE instrument_id = ANY($1) OR UPPER(code) = ANY($2) OR UPPER(symbol) = ANY($2)
+             ORDER BY instrument_id ASC",
+             &[&ids, &names]
+        ).await?;
+
+        Ok(rows.iter().map(|row| row.get("instrument_id")).collect())
     }
 
-    pub async fn search_instruments(&self, search_term: String) -> Result<Vec<Instrument>, Error> {
-        let client = Database::get_db_client().await?;
+    async fn update_instrument(&self, update: &InstrumentUpdate) -> Result<(), RepositoryError> {
+        let client = self.pool.get().await?;
 
-        let search_term = format!("%{}%", search_term);
-        print!("search_instruments {:?}",search_term );
-        let rows = client.query(
-            "SELECT instrument_id, code, symbol, last_price, prev_price, change, volume
-             FROM market_data 
-             WHERE code LIKE $1 OR symbol LIKE $1
-             LIMIT 15", 
-             &[&search_term]
+        let numeric = |value: &Decimal| PgNumeric::new(Some(value.0.clone()));
+        let updated = client.execute(
+            "UPDATE market_data SET last_price = $2, prev_price = $3, change = $4 WHERE instrument_id = $1",
+            &[&i64::from(update.instrument_id), &numeric(&update.last_price), &numeric(&update.prev_price), &numeric(&update.change)]
         ).await?;
 
-        
-        let mut new_instruments = Vec::new();
-        for row in rows {
-            let last_price: PgNumeric = row.get(3);
-            let prev_price: PgNumeric = row.get(4);
-            let change: PgNumeric = row.get(5);
-            let instrument_id: i64 = row.get("instrument_id");
-            let spark_rows = client
-                .query(
-                    "SELECT price FROM market_price_history WHERE instrument_id = $1 AND recorded_at >= NOW() - INTERVAL '24 hours' ORDER BY recorded_at ASC LIMIT 20",
-                    &[&instrument_id]
-                )
-                .await?;
-            let spark: Vec<SparkPoint> = spark_rows
-                .iter()
-                .enumerate()
-                .map(|(i, spark_row)| {
-                    let price: PgNumeric = spark_row.get("price"); 
-                    let x_value = i as f64;
-                    let y_value = price.n.unwrap().clone().to_f64().unwrap();
-                    SparkPoint {
-                        x: x_value,
-                        y: y_value,
-                    }
-                })
-                .collect();
-            let instrument = Instrument {
-                instrument_id: row.get("instrument_id"),
-                code: row.get("code"),
-                symbol: row.get("symbol"),
-                last_price: last_price.n.unwrap().clone().to_f64().unwrap(),
-                prev_price: prev_price.n.unwrap().clone().to_f64().unwrap(),
-                change: change.n.unwrap().clone().to_f64().unwrap(),
-                volume: row.get("volume"),
-                spark
-            };
-
-            new_instruments.push(instrument);
+        if updated == 0 {
+            return Err(RepositoryError::NotFound);
         }
-
-        Ok(new_instruments)
+        Ok(())
     }
 
-    pub async fn get_instrument_by_id(&self, instrument_id: i64) -> Result<InstrumentDetail, Error> {
-        let client = Database::get_db_client().await?;
+    async fn insert_chart_bar(&self, bar: &ChartData) -> Result<(), RepositoryError> {
+        let client = self.pool.get().await?;
 
-        let row = client.query_one("
-            SELECT 
-                instrument_id,
-                MAX(high_price) AS \"24High\",
-                MIN(low_price) AS \"24Low\",
-                SUM(volume) AS \"24Volume\"
-            FROM 
-                market_data_chart
-            WHERE 
-                instrument_id = $1 
-                AND timestamp >= NOW() - INTERVAL '24 HOURS'
-            GROUP BY 
-                instrument_id;
-            ", &[&instrument_id]).await?;
-
-            let high_24: PgNumeric = row.get(1);
-            let low_24:  PgNumeric = row.get(2);
-            let vol_24:  PgNumeric = row.get(3);
+        let numeric = |value: &Option<Decimal>| value.as_ref().map(|value| PgNumeric::new(Some(value.0.clone())));
+        client.execute(
+            "INSERT INTO market_data_chart (instrument_id, open_price, close_price, high_price, low_price, volume, timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[&bar.instrument_id, &numeric(&bar.open_price), &numeric(&bar.close_price), &numeric(&bar.high_price), &numeric(&bar.low_price), &numeric(&bar.volume), &bar.timestamp]
+        ).await?;
+        Ok(())
+    }
 
-            let instrument_detail = InstrumentDetail {
-                instrument_id: row.get("instrument_id"),
-                vol_24:         vol_24.n.unwrap().clone().to_f64().unwrap(),
-                high_24:        high_24.n.unwrap().clone().to_f64().unwrap(),
-                low_24:         low_24.n.unwrap().clone().to_f64().unwrap(),
-            };
-        Ok(instrument_detail)
+    fn notifies_updates(&self) -> bool {
+        true
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(Database::pool_stats(self))
     }
-    
 }
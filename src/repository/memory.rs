@@ -0,0 +1,193 @@
+use std::{collections::HashMap, fs, path::Path, sync::RwLock};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::models::decimal::Decimal;
+use crate::models::instrument::{ChartData, ChartRange, Instrument, InstrumentDetail, InstrumentKey, InstrumentListing, InstrumentUpdate, InstrumentPage, MoversRequest, SearchRequest};
+use crate::repository::repository::{with_spark, MarketDataRepository, RepositoryError};
+use crate::repository::search::SearchIndex;
+
+/// On-disk layout of a market data fixture.
+#[derive(Deserialize, Default)]
+pub struct Fixture {
+    #[serde(default)]
+    pub instruments: Vec<Instrument>,
+    #[serde(default)]
+    pub details: Vec<InstrumentDetail>,
+    #[serde(default)]
+    pub charts: Vec<ChartData>,
+}
+
+/// Repository backed by plain in-process collections, seeded from a JSON fixture.
+/// Mirrors the queries the Postgres backend runs so the API behaves the same without a database.
+pub struct InMemoryRepository {
+    instruments: RwLock<Vec<Instrument>>,
+    index: SearchIndex,
+    details: RwLock<HashMap<i64, InstrumentDetail>>,
+    charts: RwLock<HashMap<i64, Vec<ChartData>>>,
+}
+
+impl InMemoryRepository {
+    pub fn new(fixture: Fixture) -> Self {
+        let mut instruments = fixture.instruments;
+        instruments.sort_by_key(|i| i.instrument_id);
+
+        let details = fixture.details
+            .into_iter()
+            .map(|d| (d.instrument_id, d))
+            .collect();
+
+        let mut charts: HashMap<i64, Vec<ChartData>> = HashMap::new();
+        for bar in fixture.charts {
+            charts.entry(bar.instrument_id).or_default().push(bar);
+        }
+        for bars in charts.values_mut() {
+            bars.sort_by_key(|b| b.timestamp);
+        }
+
+        InMemoryRepository {
+            index: SearchIndex::new(&instruments),
+            instruments: RwLock::new(instruments),
+            details: RwLock::new(details),
+            charts: RwLock::new(charts),
+        }
+    }
+
+    pub fn from_fixture(path: impl AsRef<Path>) -> Result<Self, RepositoryError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| RepositoryError::Fixture(format!("{}: {}", path.display(), e)))?;
+        let fixture: Fixture = serde_json::from_str(&contents)
+            .map_err(|e| RepositoryError::Fixture(format!("{}: {}", path.display(), e)))?;
+
+        Ok(InMemoryRepository::new(fixture))
+    }
+}
+
+#[async_trait]
+impl MarketDataRepository for InMemoryRepository {
+    async fn load(&self, listing: &InstrumentListing) -> Result<InstrumentPage, RepositoryError> {
+        let instruments = self.instruments.read().unwrap().clone();
+        let mut page = listing.page(instruments);
+        page.items = with_spark(page.items, listing.spark);
+        Ok(page)
+    }
+
+    async fn search_instruments(&self, request: &SearchRequest) -> Result<Vec<Instrument>, RepositoryError> {
+        let ids = self.index.search(&request.term, request.limit as usize);
+        let instruments = self.instruments.read().unwrap();
+        let matches = ids
+            .iter()
+            .filter_map(|id| instruments.iter().find(|i| i.instrument_id == *id).cloned())
+            .collect();
+        Ok(with_spark(matches, request.spark))
+    }
+
+    async fn movers(&self, request: &MoversRequest) -> Result<Vec<Instrument>, RepositoryError> {
+        let instruments = self.instruments.read().unwrap().clone();
+        Ok(with_spark(request.rank(instruments), request.spark))
+    }
+
+    async fn get_instrument_detail(&self, key: &InstrumentKey) -> Result<InstrumentDetail, RepositoryError> {
+        let instrument_id = self.resolve_instruments(std::slice::from_ref(key)).await?
+            .first()
+            .copied()
+            .ok_or(RepositoryError::NotFound)?;
+        let instrument = self.instruments.read().unwrap()
+            .iter()
+            .find(|i| i.instrument_id == instrument_id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound)?;
+
+        // Instruments without fixture details have no history to report.
+        let stats = self.details.read().unwrap()
+            .get(&instrument_id)
+            .cloned()
+            .unwrap_or_else(|| InstrumentDetail { vol_24: Some(Decimal::zero()), ..InstrumentDetail::default() });
+        Ok(InstrumentDetail {
+            instrument_id,
+            code: instrument.code,
+            symbol: instrument.symbol,
+            last_price: instrument.last_price,
+            prev_price: instrument.prev_price,
+            change: instrument.change,
+            ..stats
+        })
+    }
+
+    async fn get_chart_data_by_id(&self, instrument_id: i64, range: &ChartRange) -> Result<Vec<ChartData>, RepositoryError> {
+        let charts = self.charts.read().unwrap();
+        let Some(ticks) = charts.get(&instrument_id) else {
+            return Ok(vec![]);
+        };
+
+        // Ticks are sorted by timestamp, so the first tick of a bucket opens it and the last closes it.
+        let mut bars: Vec<ChartData> = Vec::new();
+        for tick in ticks.iter().filter(|t| t.timestamp >= range.from && t.timestamp <= range.to) {
+            let bucket = range.interval.bucket_start(tick.timestamp);
+            match bars.last_mut() {
+                Some(bar) if bar.timestamp == bucket => {
+                    // Like SQL aggregates, NULLs are ignored unless every tick is NULL.
+                    bar.close_price = tick.close_price.clone();
+                    bar.high_price = bar.high_price.take().max(tick.high_price.clone());
+                    bar.low_price = match (bar.low_price.take(), &tick.low_price) {
+                        (Some(low), Some(tick_low)) => Some(low.min(tick_low.clone())),
+                        (low, tick_low) => low.or_else(|| tick_low.clone()),
+                    };
+                    bar.volume = match (bar.volume.take(), &tick.volume) {
+                        (Some(volume), Some(tick_volume)) => Some(&volume + tick_volume),
+                        (volume, tick_volume) => volume.or_else(|| tick_volume.clone()),
+                    };
+                    bar.chart_data_id = tick.chart_data_id;
+                },
+                _ => bars.push(ChartData { timestamp: bucket, ..tick.clone() }),
+            }
+        }
+
+        let skip = bars.len().saturating_sub(range.limit as usize);
+        Ok(bars.split_off(skip))
+    }
+
+    async fn get_instruments_by_ids(&self, ids: &[i64]) -> Result<Vec<Instrument>, RepositoryError> {
+        let instruments = self.instruments.read().unwrap();
+        Ok(instruments.iter().filter(|i| ids.contains(&i.instrument_id)).cloned().collect())
+    }
+
+    async fn get_all_instruments(&self) -> Result<Vec<Instrument>, RepositoryError> {
+        Ok(self.instruments.read().unwrap().clone())
+    }
+
+    async fn resolve_instruments(&self, keys: &[InstrumentKey]) -> Result<Vec<i64>, RepositoryError> {
+        let instruments = self.instruments.read().unwrap();
+        Ok(instruments
+            .iter()
+            .filter(|i| keys.iter().any(|key| match key {
+                InstrumentKey::Id(id) => i.instrument_id == *id,
+                InstrumentKey::Symbol(name) => i.code.eq_ignore_ascii_case(name) || i.symbol.eq_ignore_ascii_case(name),
+            }))
+            .map(|i| i.instrument_id)
+            .collect())
+    }
+
+    async fn update_instrument(&self, update: &InstrumentUpdate) -> Result<(), RepositoryError> {
+        let mut instruments = self.instruments.write().unwrap();
+        let instrument = instruments
+            .iter_mut()
+            .find(|i| i.instrument_id == i64::from(update.instrument_id))
+            .ok_or(RepositoryError::NotFound)?;
+        instrument.last_price = Some(update.last_price.clone());
+        instrument.prev_price = Some(update.prev_price.clone());
+        instrument.change = Some(update.change.clone());
+        Ok(())
+    }
+
+    async fn insert_chart_bar(&self, bar: &ChartData) -> Result<(), RepositoryError> {
+        let mut charts = self.charts.write().unwrap();
+        let chart_data_id = charts.values().flatten().map(|b| b.chart_data_id).max().unwrap_or(0) + 1;
+        let bars = charts.entry(bar.instrument_id).or_default();
+        // Keep each instrument's bars sorted by timestamp, as chart aggregation expects.
+        let at = bars.partition_point(|b| b.timestamp <= bar.timestamp);
+        bars.insert(at, ChartData { chart_data_id, ..bar.clone() });
+        Ok(())
+    }
+}
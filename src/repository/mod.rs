@@ -1 +1,6 @@
-pub mod database;
\ No newline at end of file
+pub mod cache;
+pub mod database;
+pub mod memory;
+#[allow(clippy::module_inception)]
+pub mod repository;
+pub mod search;
\ No newline at end of file
@@ -0,0 +1,109 @@
+use std::fmt;
+use async_trait::async_trait;
+
+use crate::models::instrument::{ChartData, ChartRange, Instrument, InstrumentDetail, InstrumentKey, InstrumentListing, InstrumentUpdate, InstrumentPage, MoversRequest, SearchRequest};
+use crate::repository::database::{DbError, PoolStats};
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    NotFound,
+    Database(DbError),
+    Fixture(String),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::NotFound => write!(f, "not found"),
+            RepositoryError::Database(e) => write!(f, "database error: {}", e),
+            RepositoryError::Fixture(e) => write!(f, "fixture error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl From<DbError> for RepositoryError {
+    fn from(e: DbError) -> Self {
+        RepositoryError::Database(e)
+    }
+}
+
+impl From<tokio_postgres::Error> for RepositoryError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        RepositoryError::Database(DbError::User(e))
+    }
+}
+
+/// Clears each instrument's spark when the caller opted out of it.
+pub fn with_spark(mut instruments: Vec<Instrument>, spark: bool) -> Vec<Instrument> {
+    if !spark {
+        for instrument in &mut instruments {
+            instrument.spark.clear();
+        }
+    }
+    instruments
+}
+
+/// Access to instruments and chart history, independent of where the data lives.
+/// List methods take `spark` as part of their request; when false, instruments come back with an empty spark and no history is read.
+#[async_trait]
+pub trait MarketDataRepository: Send + Sync {
+    /// One page of `market_data`, filtered and sorted as `listing` asks.
+    async fn load(&self, listing: &InstrumentListing) -> Result<InstrumentPage, RepositoryError>;
+
+    /// Instruments whose code or symbol matches `request.term`, most relevant first.
+    async fn search_instruments(&self, request: &SearchRequest) -> Result<Vec<Instrument>, RepositoryError>;
+
+    /// The biggest movers in `request.direction`, ranked by `request.metric`.
+    async fn movers(&self, request: &MoversRequest) -> Result<Vec<Instrument>, RepositoryError>;
+
+    /// Detail of the instrument with this ID, code or symbol. Only unknown instruments are `NotFound`.
+    async fn get_instrument_detail(&self, key: &InstrumentKey) -> Result<InstrumentDetail, RepositoryError>;
+
+    /// OHLCV bars aggregated to `range.interval`, oldest first, capped to the most recent `range.limit`.
+    async fn get_chart_data_by_id(&self, instrument_id: i64, range: &ChartRange) -> Result<Vec<ChartData>, RepositoryError>;
+
+    /// Current state of each listed instrument that exists, ordered by ID.
+    async fn get_instruments_by_ids(&self, ids: &[i64]) -> Result<Vec<Instrument>, RepositoryError>;
+
+    /// Current state of every instrument, ordered by ID, as a wildcard subscriber's snapshot.
+    async fn get_all_instruments(&self) -> Result<Vec<Instrument>, RepositoryError>;
+
+    /// Resolves instrument IDs, codes or symbols (case-insensitive) to the IDs that exist.
+    async fn resolve_instruments(&self, keys: &[InstrumentKey]) -> Result<Vec<i64>, RepositoryError>;
+
+    /// `resolve_instruments` for keys read from a URL, where an all-digit code arrives as an ID:
+    /// IDs that match no instrument are looked up again as codes or symbols.
+    async fn resolve_path_keys(&self, keys: &[InstrumentKey]) -> Result<Vec<i64>, RepositoryError> {
+        let mut ids = self.resolve_instruments(keys).await?;
+        let retry: Vec<InstrumentKey> = keys
+            .iter()
+            .filter(|key| matches!(key, InstrumentKey::Id(id) if !ids.contains(id)))
+            .filter_map(InstrumentKey::as_symbol)
+            .collect();
+        if !retry.is_empty() {
+            ids.extend(self.resolve_instruments(&retry).await?);
+            ids.sort_unstable();
+            ids.dedup();
+        }
+        Ok(ids)
+    }
+
+    /// Stores a validated price update in `market_data`. Unknown instruments are `NotFound`.
+    async fn update_instrument(&self, update: &InstrumentUpdate) -> Result<(), RepositoryError>;
+
+    /// Appends a bar to `market_data_chart`, assigning its `chart_data_id`.
+    async fn insert_chart_bar(&self, bar: &ChartData) -> Result<(), RepositoryError>;
+
+    /// Whether stored updates are broadcast by the backend itself (Postgres NOTIFY),
+    /// so writers must not publish them to the feed again.
+    fn notifies_updates(&self) -> bool {
+        false
+    }
+
+    /// Connection pool saturation, for backends that hold one.
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+}
@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::models::instrument::Instrument;
+
+/// Trigram similarity a fuzzy match needs, the same default as pg_trgm.
+pub const SIMILARITY_THRESHOLD: f64 = 0.3;
+
+/// How well an instrument matched, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Tier {
+    ExactCode,
+    ExactSymbol,
+    CodePrefix,
+    SymbolPrefix,
+    Substring,
+    Fuzzy,
+}
+
+struct Entry {
+    instrument_id: i64,
+    code: String,
+    symbol: String,
+    symbol_words: Vec<String>,
+    trigrams: HashSet<String>,
+}
+
+/// Case-insensitive, typo-tolerant lookup over instrument codes and symbols.
+/// Ranks exact code hits first, then exact symbol hits, prefixes, substrings and finally fuzzy matches.
+#[derive(Default)]
+pub struct SearchIndex {
+    entries: Vec<Entry>,
+}
+
+impl SearchIndex {
+    pub fn new(instruments: &[Instrument]) -> Self {
+        let entries = instruments
+            .iter()
+            .map(|instrument| {
+                let code = instrument.code.to_lowercase();
+                let symbol = instrument.symbol.to_lowercase();
+                let mut trigrams = trigrams_of(&code);
+                trigrams.extend(trigrams_of(&symbol));
+                Entry {
+                    instrument_id: instrument.instrument_id,
+                    symbol_words: words(&symbol).map(str::to_string).collect(),
+                    code,
+                    symbol,
+                    trigrams,
+                }
+            })
+            .collect();
+        SearchIndex { entries }
+    }
+
+    /// IDs of the best `limit` matches for `term`, best first. An empty term matches everything, in code order.
+    pub fn search(&self, term: &str, limit: usize) -> Vec<i64> {
+        let term = term.trim().to_lowercase();
+        if term.is_empty() {
+            let mut entries: Vec<&Entry> = self.entries.iter().collect();
+            entries.sort_by(|a, b| a.code.cmp(&b.code).then(a.instrument_id.cmp(&b.instrument_id)));
+            return entries.into_iter().take(limit).map(|e| e.instrument_id).collect();
+        }
+
+        let term_trigrams = trigrams_of(&term);
+        let mut matches: Vec<(Tier, f64, &Entry)> = self.entries
+            .iter()
+            .filter_map(|entry| {
+                let similarity = similarity(&term_trigrams, &entry.trigrams);
+                tier(entry, &term, similarity).map(|tier| (tier, similarity, entry))
+            })
+            .collect();
+
+        matches.sort_by(|(a_tier, a_similarity, a), (b_tier, b_similarity, b)| {
+            a_tier.cmp(b_tier)
+                .then(b_similarity.partial_cmp(a_similarity).unwrap_or(Ordering::Equal))
+                .then(a.code.cmp(&b.code))
+                .then(a.instrument_id.cmp(&b.instrument_id))
+        });
+        matches.into_iter().take(limit).map(|(_, _, entry)| entry.instrument_id).collect()
+    }
+}
+
+fn tier(entry: &Entry, term: &str, similarity: f64) -> Option<Tier> {
+    if entry.code == term {
+        Some(Tier::ExactCode)
+    } else if entry.symbol == term {
+        Some(Tier::ExactSymbol)
+    } else if entry.code.starts_with(term) {
+        Some(Tier::CodePrefix)
+    } else if entry.symbol.starts_with(term) || entry.symbol_words.iter().any(|w| w.starts_with(term)) {
+        Some(Tier::SymbolPrefix)
+    } else if entry.code.contains(term) || entry.symbol.contains(term) {
+        Some(Tier::Substring)
+    } else if similarity >= SIMILARITY_THRESHOLD || is_typo_of(term, &entry.code) {
+        Some(Tier::Fuzzy)
+    } else {
+        None
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty())
+}
+
+/// pg_trgm-style trigrams of one word: padded with two spaces in front and one behind.
+fn trigrams(word: &str) -> HashSet<String> {
+    let padded: Vec<char> = format!("  {} ", word).chars().collect();
+    padded.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+fn trigrams_of(text: &str) -> HashSet<String> {
+    words(text).flat_map(trigrams).collect()
+}
+
+/// Shared trigrams over all distinct trigrams, between 0 and 1.
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let shared = a.intersection(b).count();
+    let total = a.len() + b.len() - shared;
+    if total == 0 { 0.0 } else { shared as f64 / total as f64 }
+}
+
+/// Codes are too short for trigrams to catch a transposed or mistyped letter,
+/// so allow one edit for terms of 3+ characters and two from 6.
+fn is_typo_of(term: &str, code: &str) -> bool {
+    let allowed = match term.chars().count() {
+        0..=2 => return false,
+        3..=5 => 1,
+        _ => 2,
+    };
+    edit_distance(term, code) <= allowed
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and adjacent transpositions.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(instruments: &[(i64, &str, &str)]) -> SearchIndex {
+        let instruments: Vec<Instrument> = instruments
+            .iter()
+            .map(|(instrument_id, code, symbol)| Instrument {
+                instrument_id: *instrument_id,
+                code: code.to_string(),
+                symbol: symbol.to_string(),
+                last_price: None,
+                prev_price: None,
+                change: None,
+                volume: None,
+                spark: vec![],
+            })
+            .collect();
+        SearchIndex::new(&instruments)
+    }
+
+    #[test]
+    fn exact_code_ranks_before_exact_symbol() {
+        let index = index(&[(1, "APPL", "App"), (2, "APP", "AppLovin Corp.")]);
+        assert_eq!(index.search("app", 10), vec![2, 1]);
+        assert_eq!(index.search("  APP ", 10), vec![2, 1]);
+    }
+
+    #[test]
+    fn prefixes_rank_before_substrings() {
+        let index = index(&[(1, "MSFT", "Microsoft Corp."), (2, "XYZ", "Soft Holdings"), (3, "SOFI", "SoFi Technologies")]);
+        assert_eq!(index.search("sof", 10), vec![3, 2, 1]);
+        // Any word of the symbol counts as a prefix.
+        assert_eq!(index.search("hold", 10), vec![2]);
+    }
+
+    #[test]
+    fn typos_match_codes() {
+        let index = index(&[(1, "MSFT", "Microsoft Corp."), (2, "NVDA", "NVIDIA Corp.")]);
+        assert_eq!(index.search("mstf", 10), vec![1]);
+        assert_eq!(index.search("nvdia", 10), vec![2]);
+        assert!(index.search("qqqq", 10).is_empty());
+    }
+
+    #[test]
+    fn typo_allowance_grows_with_the_term() {
+        assert_eq!(edit_distance("msft", "msft"), 0);
+        assert_eq!(edit_distance("mstf", "msft"), 1);
+        assert_eq!(edit_distance("nvdia", "nvda"), 1);
+        assert_eq!(edit_distance("micrsft", "microsoft"), 2);
+
+        assert!(is_typo_of("mstf", "msft"));
+        assert!(!is_typo_of("nvxx", "nvda"));
+        assert!(is_typo_of("micrsft", "microsoft"));
+        assert!(!is_typo_of("mcrsft", "microsoft"));
+        assert!(!is_typo_of("ms", "mt"));
+    }
+
+    #[test]
+    fn empty_term_lists_everything_in_code_order() {
+        let index = index(&[(1, "MSFT", "Microsoft Corp."), (2, "AAPL", "Apple Inc."), (3, "NVDA", "NVIDIA Corp.")]);
+        assert_eq!(index.search("", 10), vec![2, 1, 3]);
+        assert_eq!(index.search("   ", 2), vec![2, 1]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::models::decimal::Decimal;
+use crate::models::instrument::{InstrumentKey, InstrumentUpdate};
+use crate::repository::repository::{MarketDataRepository, RepositoryError};
+
+/// How far `change` may drift from `last_price - prev_price`, relative to the larger of the
+/// expected change and 1, before the update is rejected. Covers rounding of the stored columns,
+/// not genuinely inconsistent payloads.
+const CHANGE_TOLERANCE: &str = "0.000001";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    Malformed(String),
+    NonPositivePrice,
+    InconsistentChange { expected: Decimal, actual: Decimal },
+    UnknownInstrument(i64),
+    /// The instrument could not be looked up, so the update was neither accepted nor found unknown.
+    LookupFailed(i64),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Malformed(e) => write!(f, "malformed payload: {}", e),
+            Rejection::NonPositivePrice => write!(f, "prices must be positive"),
+            Rejection::InconsistentChange { expected, actual } => {
+                write!(f, "change {} does not match last_price - prev_price = {}", actual, expected)
+            },
+            Rejection::UnknownInstrument(id) => write!(f, "unknown instrument_id {}", id),
+            Rejection::LookupFailed(id) => write!(f, "could not check instrument_id {}", id),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ValidationStats {
+    pub accepted: u64,
+    pub rejected_malformed: u64,
+    pub rejected_price: u64,
+    pub rejected_change: u64,
+    pub rejected_unknown: u64,
+    /// Updates dropped because the repository failed, not because they were invalid.
+    pub lookup_failed: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    accepted: AtomicU64,
+    rejected_malformed: AtomicU64,
+    rejected_price: AtomicU64,
+    rejected_change: AtomicU64,
+    rejected_unknown: AtomicU64,
+    lookup_failed: AtomicU64,
+}
+
+/// Gatekeeper for price updates before they reach the feed. Every rejection is counted and logged.
+pub struct UpdateValidator {
+    repository: Arc<dyn MarketDataRepository>,
+    known_ids: RwLock<HashSet<i64>>,
+    counters: Counters,
+}
+
+impl UpdateValidator {
+    pub fn new(repository: Arc<dyn MarketDataRepository>) -> Self {
+        UpdateValidator {
+            repository,
+            known_ids: RwLock::new(HashSet::new()),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Deserializes a raw NOTIFY payload and validates it.
+    pub async fn parse(&self, payload: &str) -> Result<InstrumentUpdate, Rejection> {
+        match parse_exact(payload) {
+            Ok(update) => self.validate(update).await,
+            Err(e) => Err(self.reject(Rejection::Malformed(e), payload)),
+        }
+    }
+
+    pub async fn validate(&self, update: InstrumentUpdate) -> Result<InstrumentUpdate, Rejection> {
+        if let Err(rejection) = check_prices(&update) {
+            return Err(self.reject(rejection, &format!("{:?}", update)));
+        }
+
+        let instrument_id = i64::from(update.instrument_id);
+        match self.is_known(instrument_id).await {
+            Ok(true) => {},
+            Ok(false) => return Err(self.reject(Rejection::UnknownInstrument(instrument_id), &format!("{:?}", update))),
+            Err(e) => {
+                eprintln!("error checking instrument {}: {:?}", instrument_id, e);
+                return Err(self.reject(Rejection::LookupFailed(instrument_id), &format!("{:?}", update)));
+            },
+        }
+
+        self.counters.accepted.fetch_add(1, Ordering::Relaxed);
+        Ok(update)
+    }
+
+    pub fn stats(&self) -> ValidationStats {
+        ValidationStats {
+            accepted: self.counters.accepted.load(Ordering::Relaxed),
+            rejected_malformed: self.counters.rejected_malformed.load(Ordering::Relaxed),
+            rejected_price: self.counters.rejected_price.load(Ordering::Relaxed),
+            rejected_change: self.counters.rejected_change.load(Ordering::Relaxed),
+            rejected_unknown: self.counters.rejected_unknown.load(Ordering::Relaxed),
+            lookup_failed: self.counters.lookup_failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Known IDs are remembered, so only the first update for an instrument costs a lookup.
+    async fn is_known(&self, instrument_id: i64) -> Result<bool, RepositoryError> {
+        if self.known_ids.read().unwrap().contains(&instrument_id) {
+            return Ok(true);
+        }
+
+        let ids = self.repository.resolve_instruments(&[InstrumentKey::Id(instrument_id)]).await?;
+        let known = ids.contains(&instrument_id);
+        if known {
+            self.known_ids.write().unwrap().insert(instrument_id);
+        }
+        Ok(known)
+    }
+
+    fn reject(&self, rejection: Rejection, payload: &str) -> Rejection {
+        let counter = match rejection {
+            Rejection::Malformed(_) => &self.counters.rejected_malformed,
+            Rejection::NonPositivePrice => &self.counters.rejected_price,
+            Rejection::InconsistentChange { .. } => &self.counters.rejected_change,
+            Rejection::UnknownInstrument(_) => &self.counters.rejected_unknown,
+            Rejection::LookupFailed(_) => &self.counters.lookup_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        eprintln!("rejected price update ({}): {}", rejection, payload);
+        rejection
+    }
+}
+
+/// `json_build_object` writes numerics as JSON numbers, so prices are read from their raw text
+/// rather than through `f64`.
+fn parse_exact(payload: &str) -> Result<InstrumentUpdate, String> {
+    #[derive(Deserialize)]
+    struct RawUpdate<'a> {
+        instrument_id: i32,
+        #[serde(borrow)]
+        last_price: &'a RawValue,
+        #[serde(borrow)]
+        prev_price: &'a RawValue,
+        #[serde(borrow)]
+        change: &'a RawValue,
+    }
+
+    let raw: RawUpdate = serde_json::from_str(payload).map_err(|e| e.to_string())?;
+    let decimal = |field: &str, value: &RawValue| {
+        Decimal::from_json(value.get()).ok_or_else(|| format!("{} is not a decimal: {}", field, value.get()))
+    };
+    Ok(InstrumentUpdate {
+        instrument_id: raw.instrument_id,
+        last_price: decimal("last_price", raw.last_price)?,
+        prev_price: decimal("prev_price", raw.prev_price)?,
+        change: decimal("change", raw.change)?,
+    })
+}
+
+fn check_prices(update: &InstrumentUpdate) -> Result<(), Rejection> {
+    if !update.last_price.is_positive() || !update.prev_price.is_positive() {
+        return Err(Rejection::NonPositivePrice);
+    }
+
+    let expected = &update.last_price - &update.prev_price;
+    let drift = (&update.change - &expected).abs();
+    let tolerance: Decimal = CHANGE_TOLERANCE.parse().unwrap();
+    if drift > &tolerance * &expected.abs().max(Decimal::from(1)) {
+        return Err(Rejection::InconsistentChange { expected, actual: update.change.clone() });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,713 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::NaiveDateTime;
+use futures_util::sink::{Sink, SinkExt};
+use futures_util::stream::{SplitSink, SplitStream, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tokio::time::Instant;
+use tokio::task::JoinSet;
+use tokio_websockets::{CloseCode, Message, ServerBuilder, WebSocketStream};
+
+use crate::models::decimal::{with_number_format, NumberFormat};
+use crate::bars::Bar;
+use crate::models::instrument::{ChartInterval, InstrumentKey};
+use crate::feed::{Feed, FeedPayload};
+use crate::ingest::{IngestError, Ingestor};
+use crate::models::protocol::{ClientMessage, PublisherMessage, SequencedBar, SequencedUpdate, ServerMessage, SUBSCRIBE_ALL};
+use crate::repository::repository::MarketDataRepository;
+use crate::shutdown::Shutdown;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Replies and snapshots a subscriber may have waiting for outbox space before it is disconnected.
+const MAX_PENDING_REPLIES: usize = 64;
+
+/// The instruments a single connection wants updates for, and the bar intervals it wants for them.
+/// Shared with the HTTP event stream, so both filter the feed the same way.
+#[derive(Default)]
+pub struct Subscriptions {
+    pub all: bool,
+    pub ids: HashSet<i64>,
+    pub bars: BTreeSet<ChartInterval>,
+}
+
+impl Subscriptions {
+    pub fn wants(&self, instrument_id: i64) -> bool {
+        self.all || self.ids.contains(&instrument_id)
+    }
+
+    pub fn wants_bar(&self, bar: &Bar) -> bool {
+        self.bars.contains(&bar.interval) && self.wants(bar.instrument_id)
+    }
+
+    pub fn bar_intervals(&self) -> Vec<ChartInterval> {
+        self.bars.iter().copied().collect()
+    }
+}
+
+/// A subscriber's outbox, written without waiting so a slow client never stalls its connection's loop.
+/// Replies that find it full wait in order in `pending` and go out before any feed message.
+struct Outbox {
+    tx: Sender<ServerMessage>,
+    pending: VecDeque<ServerMessage>,
+}
+
+impl Outbox {
+    fn new(tx: Sender<ServerMessage>) -> Self {
+        Outbox { tx, pending: VecDeque::new() }
+    }
+
+    /// Queues a reply, failing if the writer is gone or the client has stopped reading altogether.
+    fn queue(&mut self, message: ServerMessage) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.pending.is_empty() {
+            match self.tx.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(message)) => self.pending.push_back(message),
+                Err(TrySendError::Closed(_)) => return Err("subscriber outbox closed".into()),
+            }
+        } else {
+            self.pending.push_back(message);
+        }
+        if self.pending.len() > MAX_PENDING_REPLIES {
+            return Err("subscriber is not reading its messages".into());
+        }
+        Ok(())
+    }
+
+    fn is_blocked(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+/// Feed messages held back from a client whose outbox is full, keeping only the latest price per instrument
+/// and the latest state of each bar.
+#[derive(Default)]
+struct Conflation {
+    latest: BTreeMap<i32, SequencedUpdate>,
+    bars: BTreeMap<(i64, ChartInterval, NaiveDateTime), SequencedBar>,
+    /// First sequence number not delivered in order. Conflation is active while this is set.
+    from_seq: Option<u64>,
+    to_seq: u64,
+    /// Updates and bars folded in, including the ones later replaced.
+    received: u64,
+    /// Updates overwritten in the broadcast channel before this client read them.
+    lost: u64,
+    /// The feed reported a gap while conflating.
+    gap: bool,
+}
+
+impl Conflation {
+    fn is_active(&self) -> bool {
+        self.from_seq.is_some()
+    }
+
+    /// Whether the next message must be held back: conflation is under way, replies are waiting,
+    /// or the outbox just filled up.
+    fn holds_back(&self, outbox: &Outbox, feed: &Feed) -> bool {
+        if self.is_active() {
+            return true;
+        }
+        // Only the connection's own task sends, so spare capacity cannot vanish before it does.
+        if outbox.is_blocked() || outbox.tx.capacity() == 0 {
+            feed.record_lag();
+            return true;
+        }
+        false
+    }
+
+    fn add(&mut self, update: SequencedUpdate) {
+        self.from_seq.get_or_insert(update.seq);
+        self.to_seq = update.seq;
+        self.received += 1;
+        self.latest.insert(update.instrument.instrument_id, update);
+    }
+
+    fn add_bar(&mut self, bar: SequencedBar) {
+        self.from_seq.get_or_insert(bar.seq);
+        self.to_seq = bar.seq;
+        self.received += 1;
+        self.bars.insert((bar.bar.instrument_id, bar.bar.interval, bar.bar.timestamp), bar);
+    }
+
+    /// Records a gap reported by the feed, so the resync is followed by a snapshot.
+    fn add_gap(&mut self, seq: u64) {
+        self.from_seq.get_or_insert(seq);
+        self.to_seq = seq;
+        self.gap = true;
+    }
+
+    /// Records `count` events missed right after sequence number `after`.
+    fn lose(&mut self, after: u64, count: u64) {
+        self.from_seq.get_or_insert(after + 1);
+        self.to_seq = self.to_seq.max(after + count);
+        self.lost += count;
+    }
+
+    /// Builds the resync message and resets, ending conflation.
+    fn take(&mut self) -> ServerMessage {
+        let Conflation { latest, bars, from_seq, to_seq, received, lost, .. } = std::mem::take(self);
+        let updates: Vec<SequencedUpdate> = latest.into_values().collect();
+        let bars: Vec<SequencedBar> = bars.into_values().collect();
+        ServerMessage::Resync {
+            from_seq: from_seq.unwrap_or_default(),
+            to_seq,
+            conflated: received - (updates.len() + bars.len()) as u64,
+            lost,
+            updates,
+            bars,
+        }
+    }
+}
+
+/// How long a new connection has to send its complete upgrade request.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often an incomplete upgrade request is checked again.
+const HANDSHAKE_POLL: Duration = Duration::from_millis(10);
+/// Largest upgrade request head accepted, request line and headers together.
+const MAX_HANDSHAKE_BYTES: usize = 8192;
+
+/// Handshake path for read-only subscribers. `/` is accepted as well.
+pub const SUBSCRIBE_PATH: &str = "/subscribe";
+/// Handshake path for publishers such as the OMS.
+pub const PUBLISH_PATH: &str = "/publish";
+
+/// What a connection may do, chosen by its handshake path.
+enum Role {
+    Subscriber,
+    /// Carries the client ID if the handshake already authenticated it.
+    Publisher(Option<String>),
+}
+
+/// The parts of a pending HTTP upgrade needed to route it.
+struct Handshake {
+    target: String,
+    authorization: Option<String>,
+}
+
+impl Handshake {
+    fn path(&self) -> &str {
+        self.target.split_once('?').map_or(&self.target, |(path, _)| path)
+    }
+
+    fn bearer_token(&self) -> Option<&str> {
+        self.authorization.as_deref()?.strip_prefix("Bearer ").map(str::trim)
+    }
+}
+
+/// Serves subscribers and publishers until `shutdown` is triggered, then closes every connection.
+pub async fn start_websocket_server(addr: SocketAddr, feed: Arc<Feed>, repository: Arc<dyn MarketDataRepository>, ingestor: Arc<Ingestor>, client_backlog: usize, shutdown: Arc<Shutdown>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Price update listening on {:?}", addr);
+
+    let mut connections = JoinSet::new();
+    loop {
+        let socket = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            // Reap finished connections so the set only holds live ones.
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = shutdown.wait() => break,
+        };
+        let feed = feed.clone();
+        let repository = repository.clone();
+        let ingestor = ingestor.clone();
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let Some(handshake) = peek_handshake(&socket).await else {
+                return Ok(());
+            };
+            let role = match handshake.path() {
+                "/" | SUBSCRIBE_PATH => Role::Subscriber,
+                PUBLISH_PATH => match handshake.bearer_token() {
+                    None => Role::Publisher(None),
+                    Some(api_key) => match ingestor.authenticate(api_key) {
+                        Some(client_id) => Role::Publisher(Some(client_id.to_string())),
+                        None => return refuse(socket, "401 Unauthorized").await,
+                    },
+                },
+                _ => return refuse(socket, "404 Not Found").await,
+            };
+            let format = query_param(&handshake.target, "numbers").and_then(NumberFormat::from_param).unwrap_or_default();
+            let ws_stream = ServerBuilder::new().accept(socket).await?;
+            match role {
+                Role::Subscriber => with_number_format(format, handle_subscriber(ws_stream, feed, repository, client_backlog, shutdown)).await,
+                Role::Publisher(client_id) => with_number_format(format, handle_publisher(ws_stream, ingestor, client_id, shutdown)).await,
+            }
+        });
+    }
+
+    // Stop accepting, then let every connection send its close frame.
+    drop(listener);
+    println!("Closing {} WebSocket connections", connections.len());
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Reads the request target and `Authorization` header of the pending HTTP upgrade without consuming it.
+/// Gives up on requests whose head is incomplete after `HANDSHAKE_TIMEOUT` or larger than `MAX_HANDSHAKE_BYTES`.
+async fn peek_handshake(socket: &TcpStream) -> Option<Handshake> {
+    let mut buf = [0u8; MAX_HANDSHAKE_BYTES];
+    let head_len = tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+        loop {
+            let n = socket.peek(&mut buf).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            if let Some(end) = buf[..n].windows(4).position(|window| window == b"\r\n\r\n") {
+                return Some(end);
+            }
+            if n == buf.len() {
+                return None;
+            }
+            // Peeking returns what has arrived so far straight away, so wait a little for the rest.
+            tokio::time::sleep(HANDSHAKE_POLL).await;
+        }
+    }).await.ok()??;
+
+    let mut lines = std::str::from_utf8(&buf[..head_len]).ok()?.lines();
+    let target = lines.next()?.split(' ').nth(1)?.to_string();
+    let authorization = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .map(|(_, value)| value.trim().to_string());
+    Some(Handshake { target, authorization })
+}
+
+/// Answers the upgrade request with a plain HTTP error instead of switching protocols.
+async fn refuse(mut socket: TcpStream, status: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Drain the request first; closing with unread data resets the connection before the client sees the response.
+    let mut buf = [0u8; 8192];
+    let _ = socket.read(&mut buf).await?;
+    let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+fn query_param<'a>(target: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = target.split_once('?')?;
+    query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+async fn send<S>(sink: &mut S, message: &ServerMessage) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    S: Sink<Message, Error = tokio_websockets::Error> + Unpin,
+{
+    let json = serde_json::to_string(message)?;
+    sink.send(Message::text(json)).await?;
+    Ok(())
+}
+
+/// Tells the client the server is going away, then reads until its close reply completes the handshake.
+async fn close_for_shutdown(ws_stream: &mut WebSocketStream<TcpStream>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ws_stream.send(Message::close(Some(CloseCode::GOING_AWAY), "server shutting down")).await?;
+    while let Some(Ok(_)) = ws_stream.next().await {}
+    Ok(())
+}
+
+/// Splits the wildcard out of a key list, leaving the keys that need resolving.
+pub fn take_wildcard(keys: Vec<InstrumentKey>) -> (bool, Vec<InstrumentKey>) {
+    let mut all = false;
+    let keys = keys
+        .into_iter()
+        .filter(|key| match key {
+            InstrumentKey::Symbol(name) if name == SUBSCRIBE_ALL => {
+                all = true;
+                false
+            },
+            _ => true,
+        })
+        .collect();
+    (all, keys)
+}
+
+/// Sends the current state of `ids`, or of every instrument when `all` is set, so quiet instruments don't sit
+/// blank until their next update. The sequence number is read before the lookup, so any update racing the query
+/// is still delivered after it.
+async fn send_snapshot(outbox: &mut Outbox, feed: &Feed, repository: &dyn MarketDataRepository, all: bool, ids: &[i64]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !all && ids.is_empty() {
+        return Ok(());
+    }
+
+    let seq = feed.last_seq();
+    let instruments = if all {
+        repository.get_all_instruments().await
+    } else {
+        repository.get_instruments_by_ids(ids).await
+    };
+    match instruments {
+        Ok(instruments) => outbox.queue(ServerMessage::Snapshot { seq, instruments }),
+        Err(e) => {
+            eprintln!("error loading snapshot: {:?}", e);
+            outbox.queue(ServerMessage::Error { message: "could not load snapshot".to_string() })
+        }
+    }
+}
+
+/// Streams the feed to a read-only client. Only subscription control messages are accepted.
+/// Messages queue in an outbox of `backlog` messages; once it is full, price updates are conflated
+/// until the client catches up and gets a resync.
+async fn handle_subscriber(ws_stream: WebSocketStream<TcpStream>, feed: Arc<Feed>, repository: Arc<dyn MarketDataRepository>, backlog: usize, shutdown: Arc<Shutdown>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (mut sink, mut stream) = ws_stream.split();
+    let (outbox, queued) = mpsc::channel(backlog);
+
+    // Reading ends on close or shutdown and drops the outbox, so writing stops once it has drained.
+    let (written, read) = tokio::join!(
+        write_outbox(&mut sink, queued),
+        read_subscriber(&mut stream, outbox, &feed, repository.as_ref(), &shutdown),
+    );
+    written?;
+    read?;
+
+    if shutdown.is_triggered() {
+        // Both halves came from the same split, so reuniting cannot fail.
+        let mut ws_stream = sink.reunite(stream).map_err(|_| "mismatched WebSocket halves")?;
+        close_for_shutdown(&mut ws_stream).await?;
+    }
+    Ok(())
+}
+
+async fn write_outbox(sink: &mut SplitSink<WebSocketStream<TcpStream>, Message>, mut queued: Receiver<ServerMessage>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    while let Some(message) = queued.recv().await {
+        send(sink, &message).await?;
+    }
+    Ok(())
+}
+
+async fn read_subscriber(stream: &mut SplitStream<WebSocketStream<TcpStream>>, outbox: Sender<ServerMessage>, feed: &Feed, repository: &dyn MarketDataRepository, shutdown: &Shutdown) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Reserving borrows the sender for the whole select, so it goes through a second handle.
+    let reserver = outbox.clone();
+    let mut outbox = Outbox::new(outbox);
+    let mut bcast_rx = feed.subscribe();
+    let mut subscriptions = Subscriptions::default();
+    let mut conflation = Conflation::default();
+    // Sequence number of the last feed event this connection saw, sent or not.
+    let mut last_seq = feed.last_seq();
+    let mut heartbeat = tokio::time::interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            incoming = stream.next() => {
+                let msg = match incoming {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(err)) => return Err(err.into()),
+                    None => return Ok(()),
+                };
+                let Some(data) = msg.as_text() else {
+                    continue;
+                };
+                let message: ClientMessage = match serde_json::from_str(data) {
+                    Ok(message) => message,
+                    Err(_) if serde_json::from_str::<PublisherMessage>(data).is_ok() => {
+                        let message = format!("subscribers cannot publish; connect to {} instead", PUBLISH_PATH);
+                        outbox.queue(ServerMessage::Error { message })?;
+                        continue;
+                    },
+                    Err(e) => {
+                        outbox.queue(ServerMessage::Error { message: format!("invalid message: {}", e) })?;
+                        continue;
+                    }
+                };
+
+                match message {
+                    ClientMessage::Subscribe { instruments, bars } => {
+                        let (all, keys) = take_wildcard(instruments);
+                        match repository.resolve_instruments(&keys).await {
+                            Ok(ids) => {
+                                subscriptions.all |= all;
+                                subscriptions.ids.extend(ids.iter().copied());
+                                subscriptions.bars.extend(bars);
+                                let bars = subscriptions.bar_intervals();
+                                outbox.queue(ServerMessage::Subscribe { instruments: ids.clone(), all: subscriptions.all, bars })?;
+                                send_snapshot(&mut outbox, feed, repository, all, &ids).await?;
+                            },
+                            Err(e) => {
+                                eprintln!("error resolving subscription: {:?}", e);
+                                outbox.queue(ServerMessage::Error { message: "could not resolve instruments".to_string() })?;
+                            }
+                        }
+                    },
+                    ClientMessage::Unsubscribe { instruments, bars } => {
+                        let (all, keys) = take_wildcard(instruments);
+                        match repository.resolve_instruments(&keys).await {
+                            Ok(ids) => {
+                                if all {
+                                    subscriptions.all = false;
+                                }
+                                for id in &ids {
+                                    subscriptions.ids.remove(id);
+                                }
+                                for interval in &bars {
+                                    subscriptions.bars.remove(interval);
+                                }
+                                let bars = subscriptions.bar_intervals();
+                                outbox.queue(ServerMessage::Unsubscribe { instruments: ids, all: subscriptions.all, bars })?;
+                            },
+                            Err(e) => {
+                                eprintln!("error resolving subscription: {:?}", e);
+                                outbox.queue(ServerMessage::Error { message: "could not resolve instruments".to_string() })?;
+                            }
+                        }
+                    },
+                    ClientMessage::Heartbeat => {
+                        outbox.queue(ServerMessage::Heartbeat)?;
+                    },
+                }
+            }
+            event = bcast_rx.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        if !conflation.is_active() {
+                            feed.record_lag();
+                        }
+                        conflation.lose(last_seq, skipped);
+                        last_seq += skipped;
+                        continue;
+                    },
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                last_seq = event.seq;
+                match event.payload {
+                    FeedPayload::Price(update) => {
+                        if !subscriptions.wants(i64::from(update.instrument_id)) {
+                            continue;
+                        }
+                        if conflation.holds_back(&outbox, feed) {
+                            conflation.add(SequencedUpdate { seq: event.seq, instrument: update });
+                        } else {
+                            outbox.queue(ServerMessage::Update { seq: event.seq, instrument: update })?;
+                        }
+                    },
+                    FeedPayload::Bars(batch) => {
+                        let closed = batch.closed.iter().map(|bar| (true, bar));
+                        let updated = batch.updated.iter().map(|bar| (false, bar));
+                        for (closed, bar) in closed.chain(updated).filter(|(_, bar)| subscriptions.wants_bar(bar)) {
+                            let bar = bar.clone();
+                            if conflation.holds_back(&outbox, feed) {
+                                conflation.add_bar(SequencedBar { seq: event.seq, closed, bar });
+                            } else if closed {
+                                outbox.queue(ServerMessage::BarClose { seq: event.seq, bar })?;
+                            } else {
+                                outbox.queue(ServerMessage::BarUpdate { seq: event.seq, bar })?;
+                            }
+                        }
+                    },
+                    FeedPayload::Gap { down_for_ms } => {
+                        if conflation.holds_back(&outbox, feed) {
+                            conflation.add_gap(event.seq);
+                        } else {
+                            outbox.queue(ServerMessage::Gap { seq: event.seq, down_for_ms })?;
+                            let ids: Vec<i64> = subscriptions.ids.iter().copied().collect();
+                            send_snapshot(&mut outbox, feed, repository, subscriptions.all, &ids).await?;
+                        }
+                    },
+                }
+            }
+            permit = reserver.reserve(), if outbox.is_blocked() || conflation.is_active() => {
+                let permit = permit?;
+                // Waiting replies go first; the resync follows once they are all out.
+                if let Some(reply) = outbox.pending.pop_front() {
+                    permit.send(reply);
+                    continue;
+                }
+                let needs_snapshot = conflation.lost > 0 || conflation.gap;
+                let resync = conflation.take();
+                if let ServerMessage::Resync { conflated, lost, .. } = &resync {
+                    feed.record_resync(*conflated, *lost);
+                }
+                permit.send(resync);
+                if needs_snapshot {
+                    let ids: Vec<i64> = subscriptions.ids.iter().copied().collect();
+                    send_snapshot(&mut outbox, feed, repository, subscriptions.all, &ids).await?;
+                }
+            }
+            _ = heartbeat.tick() => {
+                // A lagging client learns it is alive from the resync instead.
+                if !conflation.holds_back(&outbox, feed) {
+                    outbox.queue(ServerMessage::Heartbeat)?;
+                }
+            }
+            _ = shutdown.wait() => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Accepts updates from a publisher and answers each with an ack or nack.
+/// Publishers are not sent the feed, so they never see their own updates echoed back.
+async fn handle_publisher(mut ws_stream: WebSocketStream<TcpStream>, ingestor: Arc<Ingestor>, mut publisher: Option<String>, shutdown: Arc<Shutdown>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut heartbeat = tokio::time::interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+    if let Some(client_id) = &publisher {
+        send(&mut ws_stream, &ServerMessage::Auth { client_id: client_id.clone() }).await?;
+    }
+
+    loop {
+        tokio::select! {
+            incoming = ws_stream.next() => {
+                let msg = match incoming {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(err)) => return Err(err.into()),
+                    None => return Ok(()),
+                };
+                let Some(data) = msg.as_text() else {
+                    continue;
+                };
+                let message: PublisherMessage = match serde_json::from_str(data) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        send(&mut ws_stream, &ServerMessage::Error { message: format!("invalid message: {}", e) }).await?;
+                        continue;
+                    }
+                };
+
+                match message {
+                    PublisherMessage::Auth { api_key } => {
+                        match ingestor.authenticate(&api_key) {
+                            Some(client_id) => {
+                                publisher = Some(client_id.to_string());
+                                send(&mut ws_stream, &ServerMessage::Auth { client_id: client_id.to_string() }).await?;
+                            },
+                            None => {
+                                publisher = None;
+                                send(&mut ws_stream, &ServerMessage::Error { message: "unknown API key".to_string() }).await?;
+                            }
+                        }
+                    },
+                    PublisherMessage::Update(payload) => {
+                        let instrument_id = payload.instrument.instrument_id;
+                        let Some(client_id) = &publisher else {
+                            let reason = "updates require an authenticated publisher".to_string();
+                            send(&mut ws_stream, &ServerMessage::Nack { instrument_id, reason }).await?;
+                            continue;
+                        };
+                        let reply = match ingestor.ingest(client_id, payload).await {
+                            Ok(_) => ServerMessage::Ack { instrument_id },
+                            Err(e) => {
+                                if let IngestError::Repository(e) = &e {
+                                    eprintln!("error storing update from {}: {:?}", client_id, e);
+                                }
+                                ServerMessage::Nack { instrument_id, reason: e.to_string() }
+                            }
+                        };
+                        send(&mut ws_stream, &reply).await?;
+                    },
+                    PublisherMessage::Heartbeat => {
+                        send(&mut ws_stream, &ServerMessage::Heartbeat).await?;
+                    },
+                }
+            }
+            _ = heartbeat.tick() => {
+                send(&mut ws_stream, &ServerMessage::Heartbeat).await?;
+            }
+            _ = shutdown.wait() => {
+                return close_for_shutdown(&mut ws_stream).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use crate::models::decimal::Decimal;
+    use crate::models::instrument::InstrumentUpdate;
+
+    fn update(seq: u64, instrument_id: i32, price: i64) -> SequencedUpdate {
+        let price = Decimal::from(price);
+        SequencedUpdate {
+            seq,
+            instrument: InstrumentUpdate { instrument_id, last_price: price.clone(), prev_price: price, change: Decimal::zero() },
+        }
+    }
+
+    fn bar(seq: u64, instrument_id: i64, minute: u32, close: i64) -> SequencedBar {
+        let price = Decimal::from(close);
+        SequencedBar {
+            seq,
+            closed: false,
+            bar: Bar {
+                instrument_id,
+                interval: ChartInterval::Min1,
+                timestamp: NaiveDate::from_ymd_opt(2024, 1, 2).unwrap().and_hms_opt(9, minute, 0).unwrap(),
+                open_price: price.clone(),
+                high_price: price.clone(),
+                low_price: price.clone(),
+                close_price: price,
+                volume: None,
+                ticks: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn conflation_keeps_the_latest_update_per_instrument() {
+        let mut conflation = Conflation::default();
+        assert!(!conflation.is_active());
+        conflation.add(update(5, 1, 10));
+        conflation.add(update(6, 2, 20));
+        conflation.add(update(7, 1, 11));
+        conflation.add(update(8, 1, 12));
+        assert!(conflation.is_active());
+
+        let ServerMessage::Resync { from_seq, to_seq, conflated, lost, updates, bars } = conflation.take() else {
+            panic!("expected a resync");
+        };
+        assert_eq!((from_seq, to_seq, conflated, lost), (5, 8, 2, 0));
+        let latest: Vec<(u64, i32, Decimal)> = updates.into_iter().map(|u| (u.seq, u.instrument.instrument_id, u.instrument.last_price)).collect();
+        assert_eq!(latest, vec![(8, 1, Decimal::from(12)), (6, 2, Decimal::from(20))]);
+        assert!(bars.is_empty());
+
+        // Taking the resync ends conflation.
+        assert!(!conflation.is_active());
+    }
+
+    #[test]
+    fn conflation_keeps_the_latest_state_of_each_bar() {
+        let mut conflation = Conflation::default();
+        conflation.add_bar(bar(1, 1, 30, 10));
+        conflation.add_bar(bar(2, 1, 30, 11));
+        conflation.add_bar(bar(3, 1, 31, 12));
+
+        let ServerMessage::Resync { conflated, bars, .. } = conflation.take() else {
+            panic!("expected a resync");
+        };
+        assert_eq!(conflated, 1);
+        let kept: Vec<(u64, Decimal)> = bars.into_iter().map(|b| (b.seq, b.bar.close_price)).collect();
+        assert_eq!(kept, vec![(2, Decimal::from(11)), (3, Decimal::from(12))]);
+    }
+
+    #[test]
+    fn lost_events_widen_the_resync_range() {
+        let mut conflation = Conflation::default();
+        conflation.lose(10, 5);
+        conflation.add(update(16, 1, 10));
+        let ServerMessage::Resync { from_seq, to_seq, lost, updates, .. } = conflation.take() else {
+            panic!("expected a resync");
+        };
+        assert_eq!((from_seq, to_seq, lost, updates.len()), (11, 16, 5, 1));
+    }
+
+    #[test]
+    fn a_full_outbox_starts_conflation() {
+        let feed = Feed::new(16, 16);
+        let (tx, _queued) = mpsc::channel(1);
+        let mut outbox = Outbox::new(tx);
+        let conflation = Conflation::default();
+        assert!(!conflation.holds_back(&outbox, &feed));
+
+        outbox.queue(ServerMessage::Heartbeat).unwrap();
+        assert!(conflation.holds_back(&outbox, &feed));
+
+        // Replies that find it full wait in order instead of failing.
+        outbox.queue(ServerMessage::Heartbeat).unwrap();
+        assert!(outbox.is_blocked());
+    }
+}